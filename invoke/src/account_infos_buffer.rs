@@ -0,0 +1,139 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+};
+
+/// An owned, contiguous array of account infos, filled by the caller and
+/// handed to the syscall as-is by [`invoke_signed_buffer`].
+///
+/// The runtime reads the account infos of the Rust ABI as an array of
+/// `AccountInfo`, so the element layout is `AccountInfo`'s own and no buffer
+/// can change it. What the buffer guarantees is the array: the infos are
+/// cloned (bumping their `Rc`s, never copying lamports or data) into one
+/// `Vec`, whose pointer and length are what the syscall gets, however the
+/// caller gathered them.
+#[derive(Clone, Default)]
+pub struct AccountInfosBuffer<'info> {
+    account_infos: Vec<AccountInfo<'info>>,
+}
+
+impl<'info> AccountInfosBuffer<'info> {
+    pub fn new() -> AccountInfosBuffer<'info> {
+        AccountInfosBuffer::default()
+    }
+
+    pub fn with_capacity(capacity: usize) -> AccountInfosBuffer<'info> {
+        AccountInfosBuffer {
+            account_infos: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Appends a clone of `account_info`.
+    pub fn push(&mut self, account_info: &AccountInfo<'info>) -> &mut Self {
+        self.account_infos.push(account_info.clone());
+        self
+    }
+
+    #[inline(always)]
+    pub fn len(&self) -> usize {
+        self.account_infos.len()
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.account_infos.is_empty()
+    }
+
+    /// The account infos, in the order they were pushed.
+    #[inline(always)]
+    pub fn account_infos(&self) -> &[AccountInfo<'info>] {
+        &self.account_infos
+    }
+}
+
+impl<'info> From<&[AccountInfo<'info>]> for AccountInfosBuffer<'info> {
+    fn from(account_infos: &[AccountInfo<'info>]) -> AccountInfosBuffer<'info> {
+        AccountInfosBuffer {
+            account_infos: account_infos.to_vec(),
+        }
+    }
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), with the account infos
+/// of an [`AccountInfosBuffer`].
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_buffer(
+    instruction: &Instruction,
+    account_infos: &AccountInfosBuffer,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    crate::invoke_signed(instruction, account_infos.account_infos(), signers_seeds)
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use solana_program::{instruction::AccountMeta, pubkey::Pubkey};
+
+    use super::*;
+
+    #[test]
+    fn test_invoke_signed_buffer() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [10u64; 2];
+        let mut data = [[0u8; 4]; 2];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[1],
+            vec![
+                AccountMeta::new(keys[0], true),
+                AccountMeta::new(keys[1], false),
+            ],
+        );
+
+        thread_local! {
+            static SEEN: core::cell::RefCell<Vec<Vec<Pubkey>>> = const { core::cell::RefCell::new(Vec::new()) };
+        }
+        // Moves one lamport and records the keys of the account infos
+        crate::mock::set_mock_handler(|_, account_infos, _| {
+            **account_infos[0].try_borrow_mut_lamports()? -= 1;
+            **account_infos[1].try_borrow_mut_lamports()? += 1;
+            account_infos[1].try_borrow_mut_data()?[0] += 1;
+            SEEN.with_borrow_mut(|seen| seen.push(account_infos.iter().map(|a| *a.key).collect()));
+            Ok(())
+        });
+        let mut buffer = AccountInfosBuffer::with_capacity(2);
+        buffer.push(&account_infos[0]).push(&account_infos[1]);
+        let from_slice = crate::invoke_signed(&instruction, &account_infos, &[]);
+        let from_buffer = invoke_signed_buffer(&instruction, &buffer, &[]);
+        let from_converted = invoke_signed_buffer(
+            &instruction,
+            &AccountInfosBuffer::from(account_infos.as_slice()),
+            &[],
+        );
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(from_slice, Ok(()));
+        assert_eq!(from_buffer, from_slice);
+        assert_eq!(from_converted, from_slice);
+        let seen = SEEN.take();
+        assert_eq!(seen, [keys.to_vec(), keys.to_vec(), keys.to_vec()]);
+        // The buffer shares the lamports and data of the original infos
+        assert_eq!(account_infos[0].lamports(), 7);
+        assert_eq!(account_infos[1].lamports(), 13);
+        assert_eq!(account_infos[1].try_borrow_data().unwrap()[0], 3);
+
+        // Checked like the slice path
+        if !crate::checks::NO_BORROW_CHECK {
+            let _guard = account_infos[0].try_borrow_data().unwrap();
+            let error = Err(solana_program::program_error::ProgramError::AccountBorrowFailed);
+            assert_eq!(invoke_signed_buffer(&instruction, &buffer, &[]), error);
+            assert_eq!(
+                crate::invoke_signed(&instruction, &account_infos, &[]),
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,272 @@
+use core::mem::MaybeUninit;
+
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+};
+
+use crate::checks;
+
+/// Maximum number of accounts for which [`invoke_signed_c`] marshals the C
+/// account metas and account infos on the stack. Above this, the arrays are
+/// heap allocated.
+pub const C_ABI_STACK_ACCOUNTS: usize = 8;
+
+/// Rust representation of C's `SolInstruction`
+#[repr(C)]
+struct SolInstruction {
+    program_id: *const Pubkey,
+    accounts: *const SolAccountMeta,
+    accounts_len: u64,
+    data: *const u8,
+    data_len: u64,
+}
+
+/// Rust representation of C's `SolAccountMeta`
+#[repr(C)]
+struct SolAccountMeta {
+    pubkey: *const Pubkey,
+    is_writable: bool,
+    is_signer: bool,
+}
+
+/// Rust representation of C's `SolAccountInfo`
+#[repr(C)]
+struct SolAccountInfo {
+    key: *const Pubkey,
+    lamports: *mut u64,
+    data_len: u64,
+    data: *mut u8,
+    owner: *const Pubkey,
+    rent_epoch: u64,
+    is_signer: bool,
+    is_writable: bool,
+    executable: bool,
+}
+
+impl SolAccountMeta {
+    #[inline(always)]
+    fn new(account_meta: &AccountMeta) -> SolAccountMeta {
+        SolAccountMeta {
+            pubkey: &account_meta.pubkey,
+            is_writable: account_meta.is_writable,
+            is_signer: account_meta.is_signer,
+        }
+    }
+}
+
+impl SolAccountInfo {
+    #[inline(always)]
+    fn new(account_info: &AccountInfo) -> SolAccountInfo {
+        // We read through the RefCells without borrowing them. The runtime
+        // requires these to be the original pointers into the input region,
+        // which is exactly what the references inside the RefCells are.
+        let (data, data_len) = unsafe {
+            let data: &mut [u8] = *account_info.data.as_ptr();
+            (data.as_mut_ptr(), data.len() as u64)
+        };
+        SolAccountInfo {
+            key: account_info.key,
+            lamports: unsafe { *account_info.lamports.as_ptr() as *mut u64 },
+            data_len,
+            data,
+            owner: account_info.owner,
+            rent_epoch: account_info.rent_epoch,
+            is_signer: account_info.is_signer,
+            is_writable: account_info.is_writable,
+            executable: account_info.executable,
+        }
+    }
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), but goes through the C ABI
+/// syscall (`sol_invoke_signed_c`).
+///
+/// The C ABI requires the account metas and account infos to be marshaled
+/// into their C representations. When the instruction has at most
+/// [`C_ABI_STACK_ACCOUNTS`] account metas and at most that many account infos
+/// are passed, both arrays are built on the stack and the CPI performs no
+/// heap allocation at all. Otherwise they are heap allocated.
+///
+/// Signer seeds are passed as-is: a `&[u8]` has the same layout as C's
+/// `SolSignerSeed` and a `&[&[u8]]` the same layout as `SolSignerSeeds`.
+///
+/// If the callee reallocates one of the accounts, the runtime reports the new
+/// length through the C account info. This function writes it back into the
+/// corresponding `AccountInfo`, as the runtime does for the Rust ABI.
+///
+/// The runtime's limits are the same for both ABIs, but since the lengths
+/// are marshaled into `u64` fields here, they are checked before marshaling:
+/// more than [`MAX_CPI_INSTRUCTION_ACCOUNTS`] account metas return
+/// [`ProgramError::InvalidArgument`], as in [`invoke_signed`], and more than
+/// [`MAX_CPI_INSTRUCTION_DATA_LEN`] bytes of data return
+/// [`ProgramError::InvalidInstructionData`], where the Rust ABI functions
+/// leave the data length to the runtime, which aborts the transaction.
+///
+/// [`MAX_CPI_INSTRUCTION_ACCOUNTS`]: crate::MAX_CPI_INSTRUCTION_ACCOUNTS
+/// [`MAX_CPI_INSTRUCTION_DATA_LEN`]: crate::MAX_CPI_INSTRUCTION_DATA_LEN
+/// [`invoke_signed`]: crate::invoke_signed
+/// [`ProgramError::InvalidArgument`]: solana_program::program_error::ProgramError::InvalidArgument
+/// [`ProgramError::InvalidInstructionData`]: solana_program::program_error::ProgramError::InvalidInstructionData
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_c(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_accounts_len(&instruction.accounts)?;
+    checks::check_data_len(&instruction.data)?;
+    checks::check_borrows(&instruction.accounts, account_infos)?;
+
+    if instruction.accounts.len() <= C_ABI_STACK_ACCOUNTS
+        && account_infos.len() <= C_ABI_STACK_ACCOUNTS
+    {
+        let mut metas = [const { MaybeUninit::<SolAccountMeta>::uninit() }; C_ABI_STACK_ACCOUNTS];
+        let mut infos = [const { MaybeUninit::<SolAccountInfo>::uninit() }; C_ABI_STACK_ACCOUNTS];
+        invoke_signed_c_with(
+            instruction,
+            account_infos,
+            signers_seeds,
+            &mut metas,
+            &mut infos,
+        )
+    } else {
+        let mut metas = Vec::<SolAccountMeta>::with_capacity(instruction.accounts.len());
+        let mut infos = Vec::<SolAccountInfo>::with_capacity(account_infos.len());
+        invoke_signed_c_with(
+            instruction,
+            account_infos,
+            signers_seeds,
+            metas.spare_capacity_mut(),
+            infos.spare_capacity_mut(),
+        )
+    }
+}
+
+/// Marshals the instruction and account infos into the provided buffers,
+/// which must be at least as long as `instruction.accounts` and
+/// `account_infos` respectively, and fires the C ABI syscall.
+#[inline(always)]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+fn invoke_signed_c_with(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    metas: &mut [MaybeUninit<SolAccountMeta>],
+    infos: &mut [MaybeUninit<SolAccountInfo>],
+) -> ProgramResult {
+    let metas = &mut metas[..instruction.accounts.len()];
+    for (slot, account_meta) in metas.iter_mut().zip(instruction.accounts.iter()) {
+        slot.write(SolAccountMeta::new(account_meta));
+    }
+    let infos = &mut infos[..account_infos.len()];
+    for (slot, account_info) in infos.iter_mut().zip(account_infos.iter()) {
+        slot.write(SolAccountInfo::new(account_info));
+    }
+    // SAFETY: every element was initialized above
+    let (metas, infos) = unsafe {
+        (
+            &*(metas as *const [MaybeUninit<SolAccountMeta>] as *const [SolAccountMeta]),
+            &*(infos as *const [MaybeUninit<SolAccountInfo>] as *const [SolAccountInfo]),
+        )
+    };
+
+    let c_instruction = SolInstruction {
+        program_id: &instruction.program_id,
+        accounts: metas.as_ptr(),
+        accounts_len: metas.len() as u64,
+        data: instruction.data.as_ptr(),
+        data_len: instruction.data.len() as u64,
+    };
+
+    #[cfg(target_os = "solana")]
+    {
+        #[cfg(feature = "structured-logs")]
+        crate::log::log_cpi(
+            &instruction.program_id,
+            instruction.accounts.len(),
+            instruction.data.len(),
+        );
+
+        let (seeds_addr, seeds_len) = crate::seeds_ptr(signers_seeds);
+        let result = unsafe {
+            solana_program::syscalls::sol_invoke_signed_c(
+                &c_instruction as *const SolInstruction as *const u8,
+                infos.as_ptr() as *const u8,
+                infos.len() as u64,
+                seeds_addr,
+                seeds_len,
+            )
+        };
+
+        // Propagate reallocs performed by the callee
+        for (c_info, account_info) in infos.iter().zip(account_infos.iter()) {
+            if c_info.is_writable {
+                if let Ok(mut data) = account_info.try_borrow_mut_data() {
+                    if data.len() as u64 != c_info.data_len {
+                        // SAFETY: the runtime guarantees the account's data
+                        // region can hold the new length.
+                        *data = unsafe {
+                            core::slice::from_raw_parts_mut(c_info.data, c_info.data_len as usize)
+                        };
+                    }
+                }
+            }
+        }
+
+        let result = crate::program_result(result);
+        #[cfg(feature = "log-cpi-errors")]
+        if let Err(error) = &result {
+            crate::log::log_cpi_error(&instruction.program_id, &instruction.accounts, error);
+        }
+        result
+    }
+
+    // Off-chain, the C ABI behaves like the Rust ABI: it panics unless mocked
+    #[cfg(not(target_os = "solana"))]
+    {
+        core::hint::black_box((&c_instruction, infos));
+        crate::invoke_signed_unchecked(instruction, account_infos, signers_seeds)
+    }
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use solana_program::program_error::ProgramError;
+
+    use super::*;
+    use crate::{MAX_CPI_INSTRUCTION_ACCOUNTS, MAX_CPI_INSTRUCTION_DATA_LEN};
+
+    #[test]
+    fn test_invoke_signed_c_limits() {
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![0; MAX_CPI_INSTRUCTION_DATA_LEN],
+        };
+        assert_eq!(invoke_signed_c(&instruction, &[], &[]), Ok(()));
+
+        instruction.data.push(0);
+        assert_eq!(
+            invoke_signed_c(&instruction, &[], &[]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+
+        instruction.data.clear();
+        let key = Pubkey::new_unique();
+        instruction.accounts =
+            vec![AccountMeta::new_readonly(key, false); MAX_CPI_INSTRUCTION_ACCOUNTS];
+        assert_eq!(invoke_signed_c(&instruction, &[], &[]), Ok(()));
+
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(key, false));
+        assert_eq!(
+            invoke_signed_c(&instruction, &[], &[]),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+}
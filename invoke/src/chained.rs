@@ -0,0 +1,134 @@
+use core::mem::MaybeUninit;
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+};
+
+/// Maximum number of combined account infos for which
+/// [`invoke_signed_chained`] builds the contiguous account infos on the
+/// stack. Above this, they are heap allocated.
+pub const CHAINED_STACK_ACCOUNTS: usize = 16;
+
+/// Same as [`invoke_signed`](crate::invoke_signed), over the account infos of
+/// `a` followed by those of `b`.
+///
+/// The syscall needs a single contiguous slice of account infos, so both are
+/// copied into one buffer: a bitwise copy of `a.len() + b.len()` account
+/// infos (48 bytes each), with no `Rc` reference count updates. The buffer
+/// lives on the stack for up to [`CHAINED_STACK_ACCOUNTS`] account infos and
+/// is heap allocated above that.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_chained<'info>(
+    instruction: &Instruction,
+    a: &[AccountInfo<'info>],
+    b: &[AccountInfo<'info>],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    if a.len() + b.len() <= CHAINED_STACK_ACCOUNTS {
+        let mut buffer = [const { MaybeUninit::<AccountInfo>::uninit() }; CHAINED_STACK_ACCOUNTS];
+        crate::invoke_signed(instruction, chain(a, b, &mut buffer), signers_seeds)
+    } else {
+        let mut buffer = Vec::<AccountInfo>::with_capacity(a.len() + b.len());
+        crate::invoke_signed(
+            instruction,
+            chain(a, b, buffer.spare_capacity_mut()),
+            signers_seeds,
+        )
+    }
+}
+
+/// Copies `a` then `b` into `buffer`, which must be at least as long as both
+/// combined, and returns the initialized prefix.
+///
+/// The copies are views: they are never dropped (neither the stack array nor
+/// the spare capacity of a `Vec` drops its elements), so the `Rc`s they share
+/// with the originals are never decremented.
+#[inline(always)]
+fn chain<'a, 'info>(
+    a: &[AccountInfo<'info>],
+    b: &[AccountInfo<'info>],
+    buffer: &'a mut [MaybeUninit<AccountInfo<'info>>],
+) -> &'a [AccountInfo<'info>] {
+    let len = a.len() + b.len();
+    let buffer = &mut buffer[..len];
+    // SAFETY: `buffer` holds `a.len() + b.len()` elements, does not overlap
+    // the borrowed slices, and `MaybeUninit<T>` is layout-compatible with `T`
+    unsafe {
+        let dst = buffer.as_mut_ptr() as *mut AccountInfo<'info>;
+        core::ptr::copy_nonoverlapping(a.as_ptr(), dst, a.len());
+        core::ptr::copy_nonoverlapping(b.as_ptr(), dst.add(a.len()), b.len());
+        core::slice::from_raw_parts(dst, len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::pubkey::Pubkey;
+
+    use super::*;
+
+    #[test]
+    fn test_chain() {
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..20).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [0u64; 20];
+        let mut data = [[0u8; 0]; 20];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let (a, b) = account_infos.split_at(3);
+
+        let mut buffer = [const { MaybeUninit::<AccountInfo>::uninit() }; 20];
+        let chained = chain(a, &b[..2], &mut buffer);
+        let chained_keys: Vec<Pubkey> = chained.iter().map(|info| *info.key).collect();
+        assert_eq!(chained_keys, keys[..5]);
+        // The copies share their `RefCell`s with the originals
+        assert!(core::ptr::eq(
+            chained[4].data.as_ptr(),
+            account_infos[4].data.as_ptr()
+        ));
+
+        let mut buffer = Vec::with_capacity(20);
+        let chained = chain(a, b, buffer.spare_capacity_mut());
+        let chained_keys: Vec<Pubkey> = chained.iter().map(|info| *info.key).collect();
+        assert_eq!(chained_keys, keys);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_signed_chained() {
+        use solana_program::instruction::AccountMeta;
+
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [10u64; 2];
+        let mut data = [[0u8; 0]; 2];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new(keys[1], false),
+            ],
+            data: vec![],
+        };
+
+        // Each account is only found in one of the sub-slices
+        crate::mock::set_mock_handler(|instruction, account_infos, _| {
+            assert_eq!(account_infos.len(), 2);
+            for (account_meta, account_info) in instruction.accounts.iter().zip(account_infos) {
+                assert_eq!(account_meta.pubkey, *account_info.key);
+                **account_info.try_borrow_mut_lamports()? += 1;
+            }
+            Ok(())
+        });
+        let result =
+            invoke_signed_chained(&instruction, &account_infos[..1], &account_infos[1..], &[]);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(account_infos[0].lamports(), 11);
+        assert_eq!(account_infos[1].lamports(), 11);
+    }
+}
@@ -0,0 +1,1067 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::{MAX_SEEDS, MAX_SEED_LEN},
+    sysvar,
+};
+
+use crate::{MAX_CPI_INSTRUCTION_ACCOUNTS, MAX_CPI_INSTRUCTION_DATA_LEN, MAX_SIGNERS};
+
+/// Whether the `no-borrow-check` feature compiled out the borrow checks of
+/// the invoke functions.
+pub(crate) const NO_BORROW_CHECK: bool = cfg!(feature = "no-borrow-check");
+
+/// Returns the first account info whose key matches the account meta, if any.
+///
+/// This mirrors how the runtime resolves account metas when translating
+/// the account infos passed to the syscall.
+#[inline(always)]
+pub(crate) fn find_account_info<'a, 'info>(
+    account_meta: &AccountMeta,
+    account_infos: &'a [AccountInfo<'info>],
+) -> Option<&'a AccountInfo<'info>> {
+    account_infos
+        .iter()
+        .find(|account_info| account_meta.pubkey == *account_info.key)
+}
+
+/// Check that the account RefCells are consistent with the request
+///
+/// This is a no-op with the `no-borrow-check` feature.
+#[inline(always)]
+pub(crate) fn check_borrows(
+    account_metas: &[AccountMeta],
+    account_infos: &[AccountInfo],
+) -> ProgramResult {
+    check_borrows_reporting_index(account_metas, account_infos).map_err(|(error, _index)| error)
+}
+
+/// Same as [`check_borrows`], but resolves each account meta to its account
+/// info with `find` rather than a linear scan of the account infos.
+#[inline(always)]
+pub(crate) fn check_borrows_by<'a, 'info: 'a>(
+    account_metas: &[AccountMeta],
+    find: impl Fn(&AccountMeta) -> Option<&'a AccountInfo<'info>>,
+) -> ProgramResult {
+    check_borrows_reporting_index_by(account_metas, find).map_err(|(error, _index)| error)
+}
+
+/// Same as [`check_borrows`], but on failure also returns the index (into
+/// `account_metas`) of the account meta whose borrow failed.
+///
+/// With the `strict` feature, [`check_writable_present`] runs first,
+/// regardless of the `no-borrow-check` feature.
+#[inline(always)]
+pub(crate) fn check_borrows_reporting_index(
+    account_metas: &[AccountMeta],
+    account_infos: &[AccountInfo],
+) -> Result<(), (ProgramError, usize)> {
+    check_borrows_reporting_index_by(account_metas, |account_meta| {
+        find_account_info(account_meta, account_infos)
+    })
+}
+
+/// Same as [`check_borrows_reporting_index`], but resolves each account
+/// meta to its account info with `find`.
+#[inline(always)]
+pub(crate) fn check_borrows_reporting_index_by<'a, 'info: 'a>(
+    account_metas: &[AccountMeta],
+    find: impl Fn(&AccountMeta) -> Option<&'a AccountInfo<'info>>,
+) -> Result<(), (ProgramError, usize)> {
+    #[cfg(feature = "strict")]
+    check_writable_present(account_metas, &find)?;
+
+    if NO_BORROW_CHECK {
+        return Ok(());
+    }
+
+    check_borrows_indexed_by(account_metas, find).inspect_err(|(_error, _index)| {
+        #[cfg(feature = "log-borrow-failures")]
+        log_borrow_failure(account_metas, *_index);
+    })
+}
+
+/// The borrow check itself, regardless of the `no-borrow-check` feature and
+/// without logging, returning the index of the account meta whose borrow
+/// failed.
+#[inline(always)]
+pub(crate) fn check_borrows_indexed(
+    account_metas: &[AccountMeta],
+    account_infos: &[AccountInfo],
+) -> Result<(), (ProgramError, usize)> {
+    check_borrows_indexed_by(account_metas, |account_meta| {
+        find_account_info(account_meta, account_infos)
+    })
+}
+
+/// Same as [`check_borrows_indexed`], but resolves each account meta to its
+/// account info with `find`.
+#[inline(always)]
+fn check_borrows_indexed_by<'a, 'info: 'a>(
+    account_metas: &[AccountMeta],
+    find: impl Fn(&AccountMeta) -> Option<&'a AccountInfo<'info>>,
+) -> Result<(), (ProgramError, usize)> {
+    for (index, account_meta) in account_metas.iter().enumerate() {
+        if let Some(account_info) = find(account_meta) {
+            check_borrow(account_meta, account_info).map_err(|error| (error, index))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that every writable account meta has an account info (resolved
+/// with `find`) that is writable too, returning the index of the first
+/// offending account meta with [`ProgramError::NotEnoughAccountKeys`] if its
+/// account info is missing, or [`ProgramError::InvalidArgument`] if it is
+/// readonly. The key
+/// of the offending account is logged.
+///
+/// The runtime rejects both, but with an error that doesn't say which
+/// account is at fault.
+#[cfg(feature = "strict")]
+pub(crate) fn check_writable_present<'a, 'info: 'a>(
+    account_metas: &[AccountMeta],
+    find: impl Fn(&AccountMeta) -> Option<&'a AccountInfo<'info>>,
+) -> Result<(), (ProgramError, usize)> {
+    for (index, account_meta) in account_metas.iter().enumerate() {
+        if !account_meta.is_writable {
+            continue;
+        }
+        match find(account_meta) {
+            None => {
+                crate::log::log(&format!(
+                    "writable account {} (index {index}) is missing from the account infos",
+                    account_meta.pubkey
+                ));
+                return Err((ProgramError::NotEnoughAccountKeys, index));
+            }
+            Some(account_info) if !account_info.is_writable => {
+                crate::log::log(&format!(
+                    "writable account {} (index {index}) is readonly in the account infos",
+                    account_meta.pubkey
+                ));
+                return Err((ProgramError::InvalidArgument, index));
+            }
+            Some(_) => {}
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "log-borrow-failures")]
+#[cold]
+fn log_borrow_failure(account_metas: &[AccountMeta], index: usize) {
+    crate::log::log(&format!(
+        "account borrow failed: index {} ({})",
+        index, account_metas[index].pubkey
+    ));
+}
+
+/// Same as [`check_borrows`], but relaxed for readonly account metas:
+///
+/// - executable accounts (e.g. the callee) and sysvars are skipped entirely,
+///   since callers essentially never hold mutable borrows on them,
+/// - all other readonly accounts only have their data borrow checked.
+///
+/// Writable account metas are checked exactly as in [`check_borrows`].
+///
+/// This is a no-op with the `no-borrow-check` feature.
+#[inline(always)]
+pub(crate) fn check_borrows_fast(
+    account_metas: &[AccountMeta],
+    account_infos: &[AccountInfo],
+) -> ProgramResult {
+    if NO_BORROW_CHECK {
+        return Ok(());
+    }
+
+    for account_meta in account_metas.iter() {
+        if let Some(account_info) = find_account_info(account_meta, account_infos) {
+            if account_meta.is_writable {
+                check_borrow(account_meta, account_info)?;
+            } else if !account_info.executable && *account_info.owner != sysvar::ID {
+                let _ = account_info.try_borrow_data()?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[inline(always)]
+pub(crate) fn check_borrow(
+    account_meta: &AccountMeta,
+    account_info: &AccountInfo,
+) -> ProgramResult {
+    if account_meta.is_writable {
+        let _ = account_info.try_borrow_mut_lamports()?;
+        let _ = account_info.try_borrow_mut_data()?;
+    } else {
+        let _ = account_info.try_borrow_lamports()?;
+        let _ = account_info.try_borrow_data()?;
+    }
+    Ok(())
+}
+
+/// Check that the instruction data is at most
+/// [`MAX_CPI_INSTRUCTION_DATA_LEN`] bytes long, returning
+/// [`ProgramError::InvalidInstructionData`] otherwise.
+#[inline(always)]
+pub(crate) fn check_data_len(data: &[u8]) -> ProgramResult {
+    if data.len() > MAX_CPI_INSTRUCTION_DATA_LEN {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    Ok(())
+}
+
+/// Check that the instruction has at most [`MAX_CPI_INSTRUCTION_ACCOUNTS`]
+/// account metas, returning [`ProgramError::InvalidArgument`] otherwise.
+#[inline(always)]
+pub(crate) fn check_accounts_len(account_metas: &[AccountMeta]) -> ProgramResult {
+    if account_metas.len() > MAX_CPI_INSTRUCTION_ACCOUNTS {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    Ok(())
+}
+
+/// Check that every account meta has a matching account info, and that the
+/// instruction does not request write access to an account the caller does
+/// not have write access to.
+pub(crate) fn check_accounts(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+) -> ProgramResult {
+    for account_meta in instruction.accounts.iter() {
+        let account_info = find_account_info(account_meta, account_infos)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if account_meta.is_writable && !account_info.is_writable {
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the account info at each `(index, min_len)` of `expected`
+/// (indices into `account_infos`) holds at least `min_len` bytes of data.
+///
+/// Returns [`ProgramError::NotEnoughAccountKeys`] if an index is out of
+/// bounds, [`ProgramError::AccountBorrowFailed`] if an account's data is
+/// mutably borrowed and [`ProgramError::AccountDataTooSmall`] if it is too
+/// small.
+pub(crate) fn check_data_lens(
+    account_infos: &[AccountInfo],
+    expected: &[(usize, usize)],
+) -> ProgramResult {
+    for &(index, min_len) in expected {
+        let account_info = account_infos
+            .get(index)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if account_info.try_borrow_data()?.len() < min_len {
+            return Err(ProgramError::AccountDataTooSmall);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that no two account infos share a key, returning
+/// [`ProgramError::InvalidArgument`] otherwise.
+///
+/// This compares every pair of keys, i.e. `n * (n - 1) / 2` 32-byte
+/// comparisons, without allocating.
+pub(crate) fn check_unique_keys(account_infos: &[AccountInfo]) -> ProgramResult {
+    for (i, account_info) in account_infos.iter().enumerate() {
+        if account_infos[i + 1..]
+            .iter()
+            .any(|other| other.key == account_info.key)
+        {
+            return Err(ProgramError::InvalidArgument);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that `lamports` make an account of `space` bytes rent-exempt, with
+/// the `Rent` sysvar.
+///
+/// Returns [`ProgramError::AccountNotRentExempt`] otherwise, or the error of
+/// `Rent::get` if the sysvar is unavailable.
+pub(crate) fn check_rent_exempt(lamports: u64, space: u64) -> ProgramResult {
+    use solana_program::sysvar::{rent::Rent, Sysvar};
+
+    let rent = Rent::get()?;
+    if !rent.is_exempt(lamports, space as usize) {
+        return Err(ProgramError::AccountNotRentExempt);
+    }
+
+    Ok(())
+}
+
+/// Check that each indexed account info is owned by the expected program.
+///
+/// Returns [`ProgramError::NotEnoughAccountKeys`] if an index is out of
+/// bounds and [`ProgramError::IllegalOwner`] on the first owner mismatch.
+pub(crate) fn check_owners(
+    account_infos: &[AccountInfo],
+    expected: &[(usize, solana_program::pubkey::Pubkey)],
+) -> ProgramResult {
+    for (index, owner) in expected.iter() {
+        let account_info = account_infos
+            .get(*index)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if account_info.owner != owner {
+            return Err(ProgramError::IllegalOwner);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that the account info of the program being invoked is executable.
+///
+/// Returns [`ProgramError::NotEnoughAccountKeys`] if no account info matches
+/// `program_id` and [`ProgramError::InvalidAccountData`] if it is not
+/// executable.
+pub(crate) fn check_executable(
+    program_id: &solana_program::pubkey::Pubkey,
+    account_infos: &[AccountInfo],
+) -> ProgramResult {
+    let program_info = account_infos
+        .iter()
+        .find(|account_info| account_info.key == program_id)
+        .ok_or(ProgramError::NotEnoughAccountKeys)?;
+    if !program_info.executable {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    Ok(())
+}
+
+/// Check that the instruction targets the expected program, returning
+/// [`ProgramError::IncorrectProgramId`] otherwise.
+#[inline(always)]
+pub(crate) fn check_program_id(
+    instruction: &Instruction,
+    expected: &solana_program::pubkey::Pubkey,
+) -> ProgramResult {
+    if instruction.program_id != *expected {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+/// Check that the instruction targets one of the `allowed` programs,
+/// returning [`ProgramError::IncorrectProgramId`] otherwise.
+///
+/// A linear scan, which beats any lookup structure for the handful of
+/// programs a whitelist usually holds.
+#[inline(always)]
+pub(crate) fn check_program_allowed(
+    instruction: &Instruction,
+    allowed: &[solana_program::pubkey::Pubkey],
+) -> ProgramResult {
+    if !allowed.contains(&instruction.program_id) {
+        return Err(ProgramError::IncorrectProgramId);
+    }
+
+    Ok(())
+}
+
+/// Check that the instruction data starts with the expected discriminator,
+/// returning [`ProgramError::InvalidInstructionData`] otherwise.
+#[inline(always)]
+pub(crate) fn check_discriminator(data: &[u8], expected: &[u8; 8]) -> ProgramResult {
+    if !data.starts_with(expected) {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    Ok(())
+}
+
+/// Check the signer seeds against the limits enforced by the runtime.
+pub(crate) fn check_seeds(signers_seeds: &[&[&[u8]]]) -> ProgramResult {
+    if signers_seeds.len() > MAX_SIGNERS {
+        return Err(ProgramError::InvalidArgument);
+    }
+    for seeds in signers_seeds.iter() {
+        if seeds.len() > MAX_SEEDS || seeds.iter().any(|seed| seed.len() > MAX_SEED_LEN) {
+            return Err(ProgramError::MaxSeedLengthExceeded);
+        }
+    }
+
+    Ok(())
+}
+
+/// Check that every signer seed group, bump included, forms a valid (off
+/// curve) program derived address for `program_id`.
+///
+/// This does not recompute the canonical bump with `find_program_address`,
+/// which would be far too costly, so a valid but non-canonical bump passes.
+#[cfg(feature = "verify-seeds")]
+pub(crate) fn check_seeds_derive(
+    signers_seeds: &[&[&[u8]]],
+    program_id: &solana_program::pubkey::Pubkey,
+) -> ProgramResult {
+    for seeds in signers_seeds.iter() {
+        solana_program::pubkey::Pubkey::create_program_address(seeds, program_id)?;
+    }
+
+    Ok(())
+}
+
+/// Check that every signer account meta will actually be signed for: either
+/// its account info is a signer, or it is the program derived address of one
+/// of the seed groups for `program_id`, the id of the calling program.
+///
+/// Returns [`ProgramError::MissingRequiredSignature`] otherwise, which is
+/// what the runtime would fail the CPI with, or the derivation error of
+/// invalid seeds. The seed groups are only derived if some signer account
+/// meta is not a signer account info.
+#[cfg(feature = "check-signers")]
+pub(crate) fn check_signers(
+    account_metas: &[AccountMeta],
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    program_id: &solana_program::pubkey::Pubkey,
+) -> ProgramResult {
+    let mut signer_pdas = None;
+    for account_meta in account_metas.iter().filter(|meta| meta.is_signer) {
+        if find_account_info(account_meta, account_infos).is_some_and(|info| info.is_signer) {
+            continue;
+        }
+        let signer_pdas: &Vec<_> = match signer_pdas {
+            Some(ref signer_pdas) => signer_pdas,
+            None => signer_pdas.insert(
+                signers_seeds
+                    .iter()
+                    .map(|seeds| {
+                        solana_program::pubkey::Pubkey::create_program_address(seeds, program_id)
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+        };
+        if !signer_pdas.contains(&account_meta.pubkey) {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::pubkey::Pubkey;
+
+    use super::*;
+
+    #[test]
+    fn test_check_borrows_reports_index() {
+        let owner = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let mut lamports = [1u64; 3];
+        let mut data = [[0u8; 4]; 3];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+
+        // Account infos are passed in a different order than the metas
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new_readonly(keys[2], false),
+                AccountMeta::new_readonly(keys[0], false),
+                AccountMeta::new(keys[1], false),
+            ],
+            data: vec![],
+        };
+        assert_eq!(
+            check_borrows_indexed(&instruction.accounts, &account_infos),
+            Ok(())
+        );
+
+        // A shared borrow only conflicts with the writable meta at index 2
+        let guard = account_infos[1].try_borrow_data().unwrap();
+        assert_eq!(
+            check_borrows_indexed(&instruction.accounts, &account_infos),
+            Err((ProgramError::AccountBorrowFailed, 2))
+        );
+        drop(guard);
+
+        // A mutable borrow conflicts with the readonly meta at index 1
+        let guard = account_infos[0].try_borrow_mut_lamports().unwrap();
+        assert_eq!(
+            check_borrows_indexed(&instruction.accounts, &account_infos),
+            Err((ProgramError::AccountBorrowFailed, 1))
+        );
+        if !NO_BORROW_CHECK {
+            assert_eq!(
+                check_borrows(&instruction.accounts, &account_infos),
+                Err(ProgramError::AccountBorrowFailed)
+            );
+        }
+        drop(guard);
+    }
+
+    /// Borrow state of an account's lamports or data, held across a check.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Held {
+        None,
+        Shared,
+        Mutable,
+    }
+
+    /// xorshift64, so that every run checks the same cases.
+    fn next(state: &mut u64) -> u64 {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    }
+
+    fn held(state: &mut u64) -> Held {
+        match next(state) % 3 {
+            0 => Held::None,
+            1 => Held::Shared,
+            _ => Held::Mutable,
+        }
+    }
+
+    /// Random account metas against random account infos (duplicate keys,
+    /// missing keys, executables and sysvars included) with random borrows
+    /// held, checked against a model of which borrows conflict.
+    #[test]
+    fn test_check_borrows_randomized() {
+        let owners = [Pubkey::new_unique(), sysvar::ID];
+        let keys: Vec<Pubkey> = (0..6).map(|_| Pubkey::new_unique()).collect();
+        let mut state = 0x9e37_79b9_7f4a_7c15;
+
+        for _ in 0..10_000 {
+            let n_infos = (next(&mut state) % 9) as usize;
+            let n_metas = (next(&mut state) % 9) as usize;
+            let mut lamports = [1u64; 8];
+            let mut data = [[0u8; 4]; 8];
+            let mut held_lamports = Vec::with_capacity(n_infos);
+            let mut held_data = Vec::with_capacity(n_infos);
+            let account_infos: Vec<AccountInfo> = lamports
+                .iter_mut()
+                .zip(data.iter_mut())
+                .take(n_infos)
+                .map(|(lamports, data)| {
+                    held_lamports.push(held(&mut state));
+                    held_data.push(held(&mut state));
+                    AccountInfo::new(
+                        &keys[(next(&mut state) % 4) as usize],
+                        false,
+                        next(&mut state) & 1 == 0,
+                        lamports,
+                        data,
+                        &owners[(next(&mut state) % 2) as usize],
+                        next(&mut state) & 3 == 0,
+                        0,
+                    )
+                })
+                .collect();
+            // Keys 4 and 5 are never in the account infos
+            let account_metas: Vec<AccountMeta> = (0..n_metas)
+                .map(|_| {
+                    let key = keys[(next(&mut state) % 6) as usize];
+                    if next(&mut state) & 1 == 0 {
+                        AccountMeta::new(key, false)
+                    } else {
+                        AccountMeta::new_readonly(key, false)
+                    }
+                })
+                .collect();
+
+            // The first account info with a matching key is the one checked
+            let conflicts = |fast: bool| {
+                account_metas.iter().position(|account_meta| {
+                    let Some(i) = account_infos
+                        .iter()
+                        .position(|account_info| *account_info.key == account_meta.pubkey)
+                    else {
+                        return false;
+                    };
+                    let (lamports, data) = (held_lamports[i], held_data[i]);
+                    if account_meta.is_writable {
+                        lamports != Held::None || data != Held::None
+                    } else if fast {
+                        let skipped =
+                            account_infos[i].executable || *account_infos[i].owner == sysvar::ID;
+                        !skipped && data == Held::Mutable
+                    } else {
+                        lamports == Held::Mutable || data == Held::Mutable
+                    }
+                })
+            };
+            let expected = conflicts(false);
+            let expected_fast = conflicts(true);
+
+            {
+                let mut shared_lamports = Vec::new();
+                let mut mutable_lamports = Vec::new();
+                let mut shared_data = Vec::new();
+                let mut mutable_data = Vec::new();
+                for (i, account_info) in account_infos.iter().enumerate() {
+                    match held_lamports[i] {
+                        Held::None => {}
+                        Held::Shared => {
+                            shared_lamports.push(account_info.try_borrow_lamports().unwrap())
+                        }
+                        Held::Mutable => {
+                            mutable_lamports.push(account_info.try_borrow_mut_lamports().unwrap())
+                        }
+                    }
+                    match held_data[i] {
+                        Held::None => {}
+                        Held::Shared => shared_data.push(account_info.try_borrow_data().unwrap()),
+                        Held::Mutable => {
+                            mutable_data.push(account_info.try_borrow_mut_data().unwrap())
+                        }
+                    }
+                }
+
+                assert_eq!(
+                    check_borrows_indexed(&account_metas, &account_infos),
+                    expected.map_or(Ok(()), |index| Err((
+                        ProgramError::AccountBorrowFailed,
+                        index
+                    )))
+                );
+                if !NO_BORROW_CHECK {
+                    assert_eq!(
+                        check_borrows_fast(&account_metas, &account_infos),
+                        expected_fast.map_or(Ok(()), |_| Err(ProgramError::AccountBorrowFailed))
+                    );
+                }
+                // The checks leave the held borrows as they found them
+                for (i, account_info) in account_infos.iter().enumerate() {
+                    assert_eq!(
+                        account_info.try_borrow_lamports().is_ok(),
+                        held_lamports[i] != Held::Mutable
+                    );
+                    assert_eq!(
+                        account_info.try_borrow_data().is_ok(),
+                        held_data[i] != Held::Mutable
+                    );
+                }
+            }
+
+            // and take none of their own
+            for account_info in account_infos.iter() {
+                assert!(account_info.try_borrow_mut_lamports().is_ok());
+                assert!(account_info.try_borrow_mut_data().is_ok());
+            }
+        }
+    }
+
+    #[cfg(all(feature = "log-borrow-failures", feature = "mock"))]
+    #[test]
+    fn test_log_borrow_failure() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [0u64; 2];
+        let mut data = [[0u8; 1]; 2];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new_readonly(keys[0], false),
+                AccountMeta::new(keys[1], false),
+            ],
+        );
+
+        crate::mock::take_logs();
+        let _guard = account_infos[1].try_borrow_data().unwrap();
+        let result = crate::invoke(&instruction, &account_infos);
+
+        if NO_BORROW_CHECK {
+            assert_eq!(result, Ok(()));
+            let logs = crate::mock::take_logs();
+            assert!(!logs
+                .iter()
+                .any(|log| log.starts_with("account borrow failed")));
+        } else {
+            assert_eq!(result, Err(ProgramError::AccountBorrowFailed));
+            assert_eq!(
+                crate::mock::take_logs(),
+                [format!("account borrow failed: index 1 ({})", keys[1])]
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_borrows_fast() {
+        let owner = Pubkey::new_unique();
+        let (program_key, sysvar_key, readonly_key) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let mut lamports = [1u64; 3];
+        let mut data = [[0u8; 4]; 3];
+        let [l0, l1, l2] = &mut lamports;
+        let [d0, d1, d2] = &mut data;
+        let account_infos = [
+            AccountInfo::new(&program_key, false, false, l0, d0, &owner, true, 0),
+            AccountInfo::new(&sysvar_key, false, false, l1, d1, &sysvar::ID, false, 0),
+            AccountInfo::new(&readonly_key, false, false, l2, d2, &owner, false, 0),
+        ];
+        let account_metas = [
+            AccountMeta::new_readonly(program_key, false),
+            AccountMeta::new_readonly(sysvar_key, false),
+            AccountMeta::new_readonly(readonly_key, false),
+        ];
+
+        // Program and sysvar accounts are not checked
+        let program_guard = account_infos[0].try_borrow_mut_data().unwrap();
+        let sysvar_guard = account_infos[1].try_borrow_mut_data().unwrap();
+        assert_eq!(check_borrows_fast(&account_metas, &account_infos), Ok(()));
+        if NO_BORROW_CHECK {
+            return;
+        }
+        assert_eq!(
+            check_borrows(&account_metas, &account_infos),
+            Err(ProgramError::AccountBorrowFailed)
+        );
+        drop((program_guard, sysvar_guard));
+
+        // Other readonly accounts still have their data checked
+        let guard = account_infos[2].try_borrow_mut_data().unwrap();
+        assert_eq!(
+            check_borrows_fast(&account_metas, &account_infos),
+            Err(ProgramError::AccountBorrowFailed)
+        );
+        drop(guard);
+    }
+
+    #[test]
+    fn test_check_unique_keys() {
+        let owner = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let mut lamports = [0u64; 4];
+        let mut data = [[0u8; 0]; 4];
+        // The last account info duplicates the second key
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .chain(keys.get(1))
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|((key, lamports), data)| {
+                AccountInfo::new(key, false, false, lamports, data, &owner, false, 0)
+            })
+            .collect();
+
+        assert_eq!(check_unique_keys(&account_infos[..3]), Ok(()));
+        assert_eq!(
+            check_unique_keys(&account_infos),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(check_unique_keys(&[]), Ok(()));
+    }
+
+    #[test]
+    fn test_check_owners() {
+        let token_program = Pubkey::new_unique();
+        let other_owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [0u64; 2];
+        let mut data = [[0u8; 0]; 2];
+        let owners = [token_program, other_owner];
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .zip(owners.iter())
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|(((key, owner), lamports), data)| {
+                AccountInfo::new(key, false, false, lamports, data, owner, false, 0)
+            })
+            .collect();
+
+        assert_eq!(
+            check_owners(&account_infos, &[(0, token_program), (1, other_owner)]),
+            Ok(())
+        );
+        assert_eq!(
+            check_owners(&account_infos, &[(0, token_program), (1, token_program)]),
+            Err(ProgramError::IllegalOwner)
+        );
+        assert_eq!(
+            check_owners(&account_infos, &[(2, token_program)]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[test]
+    fn test_check_program_id() {
+        let from = Pubkey::new_unique();
+        let mut instruction =
+            solana_program::system_instruction::transfer(&from, &Pubkey::new_unique(), 1);
+        assert_eq!(
+            check_program_id(&instruction, &crate::SYSTEM_PROGRAM_ID),
+            Ok(())
+        );
+
+        // A fake system program
+        instruction.program_id = Pubkey::new_unique();
+        assert_eq!(
+            check_program_id(&instruction, &crate::SYSTEM_PROGRAM_ID),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn test_check_data_lens() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (mut small_lamports, mut large_lamports) = (0, 0);
+        let (mut small, mut large) = ([0u8; 8], [0u8; 165]);
+        let account_infos = [
+            AccountInfo::new(
+                &keys[0],
+                false,
+                true,
+                &mut small_lamports,
+                &mut small,
+                &owner,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &keys[1],
+                false,
+                true,
+                &mut large_lamports,
+                &mut large,
+                &owner,
+                false,
+                0,
+            ),
+        ];
+
+        assert_eq!(check_data_lens(&account_infos, &[]), Ok(()));
+        assert_eq!(check_data_lens(&account_infos, &[(0, 8), (1, 165)]), Ok(()));
+        assert_eq!(check_data_lens(&account_infos, &[(1, 100)]), Ok(()));
+        assert_eq!(
+            check_data_lens(&account_infos, &[(1, 165), (0, 9)]),
+            Err(ProgramError::AccountDataTooSmall)
+        );
+        assert_eq!(
+            check_data_lens(&account_infos, &[(2, 0)]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let _guard = account_infos[0].try_borrow_mut_data().unwrap();
+        assert_eq!(
+            check_data_lens(&account_infos, &[(0, 0)]),
+            Err(ProgramError::AccountBorrowFailed)
+        );
+    }
+
+    #[test]
+    fn test_check_program_allowed() {
+        let allowed = [crate::SYSTEM_PROGRAM_ID, Pubkey::new_unique()];
+        let mut instruction = solana_program::system_instruction::transfer(
+            &Pubkey::new_unique(),
+            &Pubkey::new_unique(),
+            1,
+        );
+        assert_eq!(check_program_allowed(&instruction, &allowed), Ok(()));
+        instruction.program_id = allowed[1];
+        assert_eq!(check_program_allowed(&instruction, &allowed), Ok(()));
+
+        instruction.program_id = Pubkey::new_unique();
+        assert_eq!(
+            check_program_allowed(&instruction, &allowed),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        assert_eq!(
+            check_program_allowed(&instruction, &[]),
+            Err(ProgramError::IncorrectProgramId)
+        );
+    }
+
+    #[test]
+    fn test_check_executable() {
+        let loader = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [0u64; 2];
+        let mut data = [[0u8; 0]; 2];
+        let executable = [true, false];
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .zip(executable.iter())
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|(((key, executable), lamports), data)| {
+                AccountInfo::new(key, false, false, lamports, data, &loader, *executable, 0)
+            })
+            .collect();
+
+        assert_eq!(check_executable(&keys[0], &account_infos), Ok(()));
+        assert_eq!(
+            check_executable(&keys[1], &account_infos),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            check_executable(&Pubkey::new_unique(), &account_infos),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[cfg(feature = "verify-seeds")]
+    #[test]
+    fn test_check_seeds_derive() {
+        let program_id = Pubkey::new_unique();
+        let (_pda, bump) = Pubkey::find_program_address(&[b"vault"], &program_id);
+        assert_eq!(
+            check_seeds_derive(&[&[b"vault", &[bump]]], &program_id),
+            Ok(())
+        );
+
+        // Roughly half of all bumps land on the curve
+        let invalid_bump = (0..=u8::MAX)
+            .find(|bump| {
+                Pubkey::create_program_address(&[b"vault", &[*bump]], &program_id).is_err()
+            })
+            .unwrap();
+        assert_eq!(
+            check_seeds_derive(
+                &[&[b"vault", &[bump]], &[b"vault", &[invalid_bump]]],
+                &program_id
+            ),
+            Err(ProgramError::InvalidSeeds)
+        );
+    }
+
+    #[cfg(feature = "strict")]
+    #[test]
+    fn test_check_writable_present() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [0u64; 2];
+        let mut data = [[0u8; 0]; 2];
+        // The first account info is writable, the second readonly
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .enumerate()
+            .map(|(i, ((key, lamports), data))| {
+                AccountInfo::new(key, false, i == 0, lamports, data, &owner, false, 0)
+            })
+            .collect();
+        let missing = Pubkey::new_unique();
+
+        let account_metas = [
+            AccountMeta::new(keys[0], false),
+            AccountMeta::new_readonly(keys[1], false),
+            // Readonly metas are not required to have an account info
+            AccountMeta::new_readonly(missing, false),
+        ];
+        assert_eq!(
+            check_writable_present(&account_metas, |meta| find_account_info(
+                meta,
+                &account_infos
+            )),
+            Ok(())
+        );
+
+        // Writable meta without an account info
+        let account_metas = [
+            AccountMeta::new(keys[0], false),
+            AccountMeta::new(missing, false),
+        ];
+        assert_eq!(
+            check_writable_present(&account_metas, |meta| find_account_info(
+                meta,
+                &account_infos
+            )),
+            Err((ProgramError::NotEnoughAccountKeys, 1))
+        );
+
+        // Writable meta for a readonly account info
+        let account_metas = [AccountMeta::new(keys[1], false)];
+        assert_eq!(
+            check_writable_present(&account_metas, |meta| find_account_info(
+                meta,
+                &account_infos
+            )),
+            Err((ProgramError::InvalidArgument, 0))
+        );
+
+        // Checked before the borrows, even when they would fail too
+        let _guard = account_infos[0].try_borrow_mut_data().unwrap();
+        let account_metas = [
+            AccountMeta::new(keys[0], false),
+            AccountMeta::new(missing, false),
+        ];
+        assert_eq!(
+            check_borrows(&account_metas, &account_infos),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+
+    #[cfg(feature = "check-signers")]
+    #[test]
+    fn test_check_signers() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (pda, bump) = Pubkey::find_program_address(&[b"vault"], &program_id);
+        let keys = [Pubkey::new_unique(), pda, Pubkey::new_unique()];
+        let mut lamports = [0u64; 3];
+        let mut data = [[0u8; 0]; 3];
+        // Only the first account info is a signer
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .enumerate()
+            .map(|(i, ((key, lamports), data))| {
+                AccountInfo::new(key, i == 0, true, lamports, data, &owner, false, 0)
+            })
+            .collect();
+        let vault_seeds: &[&[u8]] = &[b"vault", &[bump]];
+
+        // Signer account info, and a PDA covered by a seed group
+        let account_metas = [
+            AccountMeta::new(keys[0], true),
+            AccountMeta::new(keys[1], true),
+            AccountMeta::new(keys[2], false),
+        ];
+        assert_eq!(
+            check_signers(&account_metas, &account_infos, &[vault_seeds], &program_id),
+            Ok(())
+        );
+
+        // The PDA without its seeds
+        assert_eq!(
+            check_signers(&account_metas, &account_infos, &[], &program_id),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+
+        // A non-signer, non-PDA account
+        let account_metas = [AccountMeta::new(keys[2], true)];
+        assert_eq!(
+            check_signers(&account_metas, &account_infos, &[vault_seeds], &program_id),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+}
@@ -0,0 +1,100 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta, pubkey::Pubkey,
+};
+
+use crate::checks;
+
+/// An instruction with a fixed number of account metas and bytes of data,
+/// held inline instead of in two `Vec`s, for tiny and frequent CPIs such as
+/// one discriminator byte and a few accounts.
+///
+/// [`invoke_compact`] stabilizes it straight from its arrays: building and
+/// firing it never touches the heap.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CompactInstruction<const N: usize, const M: usize> {
+    pub program_id: Pubkey,
+    pub accounts: [AccountMeta; N],
+    pub data: [u8; M],
+}
+
+impl<const N: usize, const M: usize> CompactInstruction<N, M> {
+    pub const fn new(
+        program_id: Pubkey,
+        accounts: [AccountMeta; N],
+        data: [u8; M],
+    ) -> CompactInstruction<N, M> {
+        CompactInstruction {
+            program_id,
+            accounts,
+            data,
+        }
+    }
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), for a
+/// [`CompactInstruction`].
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_compact<const N: usize, const M: usize>(
+    instruction: &CompactInstruction<N, M>,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_accounts_len(&instruction.accounts)?;
+    checks::check_borrows(&instruction.accounts, account_infos)?;
+
+    crate::invoke_signed_parts_unchecked(
+        &instruction.program_id,
+        &instruction.accounts,
+        &instruction.data,
+        account_infos,
+        signers_seeds,
+    )
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use solana_program::instruction::Instruction;
+
+    use super::*;
+
+    #[test]
+    fn test_invoke_compact() {
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 0]);
+        let account_info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let compact =
+            CompactInstruction::new(Pubkey::new_unique(), [AccountMeta::new(key, false)], [9]);
+
+        crate::mock::record_invocations();
+        let result = invoke_compact(&compact, core::slice::from_ref(&account_info), &[]);
+
+        assert_eq!(result, Ok(()));
+        // The callee sees the same instruction as the `Vec`-based path
+        let expected = Instruction::new_with_bytes(
+            compact.program_id,
+            &[9],
+            vec![AccountMeta::new(key, false)],
+        );
+        assert_eq!(crate::mock::take_invocations(), [expected]);
+
+        // Checked like `invoke_signed`
+        if !checks::NO_BORROW_CHECK {
+            let _guard = account_info.try_borrow_data().unwrap();
+            assert_eq!(
+                invoke_compact(&compact, core::slice::from_ref(&account_info), &[]),
+                Err(solana_program::program_error::ProgramError::AccountBorrowFailed)
+            );
+        }
+    }
+}
@@ -0,0 +1,121 @@
+//! An off-chain guard against unbounded CPI recursion, e.g. through mock
+//! handlers standing in for composable protocols that invoke each other.
+//!
+//! Each invoke function of this crate counts the CPIs in flight on the
+//! current thread, and returns [`ProgramError::InvalidArgument`] before
+//! firing one that would exceed the maximum set with [`set_max_cpi_depth`].
+//!
+//! This is a no-op on-chain: every program invocation runs with its own
+//! memory, so a counter in the caller can't see the CPIs nested below it, and
+//! the runtime enforces its own depth limit there.
+//!
+//! [`ProgramError::InvalidArgument`]: solana_program::program_error::ProgramError::InvalidArgument
+
+#[cfg(not(target_os = "solana"))]
+use std::cell::Cell;
+
+#[cfg(not(target_os = "solana"))]
+use solana_program::program_error::ProgramError;
+
+#[cfg(not(target_os = "solana"))]
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static MAX_DEPTH: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+/// Sets the maximum number of nested CPIs on this thread. This is a no-op
+/// on-chain.
+pub fn set_max_cpi_depth(max: usize) {
+    #[cfg(not(target_os = "solana"))]
+    MAX_DEPTH.set(Some(max));
+
+    #[cfg(target_os = "solana")]
+    let _ = max;
+}
+
+/// Removes this thread's maximum. This is a no-op on-chain.
+pub fn clear_max_cpi_depth() {
+    #[cfg(not(target_os = "solana"))]
+    MAX_DEPTH.set(None);
+}
+
+/// The number of CPIs in flight on this thread, always 0 on-chain.
+pub fn cpi_depth() -> usize {
+    #[cfg(not(target_os = "solana"))]
+    return DEPTH.get();
+
+    #[cfg(target_os = "solana")]
+    0
+}
+
+/// Counts a CPI in flight until dropped.
+#[cfg(not(target_os = "solana"))]
+pub(crate) struct DepthGuard(());
+
+#[cfg(not(target_os = "solana"))]
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.set(DEPTH.get() - 1);
+    }
+}
+
+/// Counts a CPI about to be fired, or fails if it would exceed the maximum.
+#[cfg(not(target_os = "solana"))]
+pub(crate) fn enter() -> Result<DepthGuard, ProgramError> {
+    let depth = DEPTH.get() + 1;
+    if MAX_DEPTH.get().is_some_and(|max| depth > max) {
+        return Err(ProgramError::InvalidArgument);
+    }
+    DEPTH.set(depth);
+
+    Ok(DepthGuard(()))
+}
+
+#[cfg(all(test, feature = "mock", not(feature = "panic-on-cpi-error")))]
+mod tests {
+    use solana_program::{instruction::Instruction, pubkey::Pubkey};
+
+    use super::*;
+
+    thread_local! {
+        static DEEPEST: Cell<usize> = const { Cell::new(0) };
+    }
+
+    /// Invokes itself again, up to a depth of 5
+    fn recurse(
+        instruction: &Instruction,
+        account_infos: &[solana_program::account_info::AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> solana_program::entrypoint::ProgramResult {
+        DEEPEST.set(DEEPEST.get().max(cpi_depth()));
+        if cpi_depth() < 5 {
+            crate::invoke(instruction, account_infos)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_max_cpi_depth() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+        crate::mock::set_mock_handler(recurse);
+
+        // Unbounded
+        assert_eq!(crate::invoke(&instruction, &[]), Ok(()));
+        assert_eq!(DEEPEST.replace(0), 5);
+        assert_eq!(cpi_depth(), 0);
+
+        // Bounded below the recursion
+        set_max_cpi_depth(3);
+        let bounded = crate::invoke(&instruction, &[]);
+        clear_max_cpi_depth();
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(bounded, Err(ProgramError::InvalidArgument));
+        assert_eq!(DEEPEST.replace(0), 3);
+        assert_eq!(cpi_depth(), 0);
+    }
+}
@@ -0,0 +1,73 @@
+//! Off-chain instrumentation of CPIs, e.g. for test harnesses counting or
+//! inspecting the instructions a program invokes.
+
+#[cfg(not(target_os = "solana"))]
+use std::cell::Cell;
+
+use solana_program::instruction::Instruction;
+#[cfg(not(target_os = "solana"))]
+use solana_program::stable_layout::stable_instruction::StableInstruction;
+
+/// Called with each instruction before it is invoked.
+pub type InvokeHook = fn(&Instruction);
+
+#[cfg(not(target_os = "solana"))]
+thread_local! {
+    static HOOK: Cell<Option<InvokeHook>> = const { Cell::new(None) };
+}
+
+/// Sets the function called with each instruction right before it is invoked
+/// on this thread, once the checks of the invoke function passed (i.e. right
+/// before the [`mock`](crate::mock) handler, if any).
+///
+/// This is a no-op on-chain.
+pub fn set_invoke_hook(hook: InvokeHook) {
+    #[cfg(not(target_os = "solana"))]
+    HOOK.set(Some(hook));
+
+    #[cfg(target_os = "solana")]
+    let _ = hook;
+}
+
+/// Removes this thread's invoke hook. This is a no-op on-chain.
+pub fn clear_invoke_hook() {
+    #[cfg(not(target_os = "solana"))]
+    HOOK.set(None);
+}
+
+/// Calls the current hook, if any, with the stabilized instruction.
+#[cfg(not(target_os = "solana"))]
+pub(crate) fn call(instruction: &StableInstruction) {
+    if let Some(hook) = HOOK.get() {
+        hook(&crate::instruction_stabilizer::to_instruction(instruction));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::pubkey::Pubkey;
+
+    use super::*;
+
+    thread_local! {
+        static INVOKED: Cell<Option<(Pubkey, usize)>> = const { Cell::new(None) };
+    }
+
+    #[test]
+    fn test_invoke_hook() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![1, 2, 3],
+        };
+
+        set_invoke_hook(|instruction| {
+            INVOKED.set(Some((instruction.program_id, instruction.data.len())))
+        });
+        // The syscall itself panics off-chain unless mocked
+        let _ = std::panic::catch_unwind(|| crate::invoke(&instruction, &[]));
+        clear_invoke_hook();
+
+        assert_eq!(INVOKED.get(), Some((instruction.program_id, 3)));
+    }
+}
@@ -0,0 +1,153 @@
+//! Helpers that fire a CPI and then inspect the accounts it touched.
+
+use solana_program::{
+    account_info::AccountInfo, instruction::Instruction, program_error::ProgramError,
+};
+
+/// Same as [`invoke`](crate::invoke), but also reports whether the data length
+/// of any writable account info changed during the CPI, i.e. whether the
+/// callee reallocated one of them.
+///
+/// Returns [`ProgramError::AccountBorrowFailed`] without firing the CPI if
+/// the data of a writable account info is mutably borrowed.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_and_detect_realloc(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+) -> Result<bool, ProgramError> {
+    let before = writable_data_lens(account_infos)?;
+    crate::invoke(instruction, account_infos)?;
+
+    Ok(writable_data_lens(account_infos)? != before)
+}
+
+/// Same as [`invoke`](crate::invoke), then returns the data lengths of the
+/// account infos at the `watch` indices (into `account_infos`), in the same
+/// order, e.g. to follow accounts the callee may reallocate.
+///
+/// Only the watched account infos are read. Returns
+/// [`ProgramError::NotEnoughAccountKeys`] without firing the CPI if an index
+/// is out of bounds.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_and_lengths(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    watch: &[usize],
+) -> Result<Vec<usize>, ProgramError> {
+    if watch.iter().any(|&index| index >= account_infos.len()) {
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    crate::invoke(instruction, account_infos)?;
+
+    watch
+        .iter()
+        .map(|&index| data_len(&account_infos[index]))
+        .collect()
+}
+
+#[inline(always)]
+fn writable_data_lens(account_infos: &[AccountInfo]) -> Result<Vec<usize>, ProgramError> {
+    account_infos
+        .iter()
+        .filter(|account_info| account_info.is_writable)
+        .map(data_len)
+        .collect()
+}
+
+/// Same as `AccountInfo::data_len`, returning an error instead of panicking
+/// if the data is mutably borrowed.
+#[inline(always)]
+fn data_len(account_info: &AccountInfo) -> Result<usize, ProgramError> {
+    Ok(account_info.try_borrow_data()?.len())
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use solana_program::{instruction::AccountMeta, pubkey::Pubkey};
+
+    use super::*;
+
+    #[test]
+    fn test_invoke_and_detect_realloc() {
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 8]);
+        let account_info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let account_infos = [account_info];
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(key, false)],
+            data: vec![],
+        };
+
+        // Writes to the account without reallocating it
+        crate::mock::set_mock_handler(|_, account_infos, _| {
+            account_infos[0].try_borrow_mut_data()?[0] = 1;
+            Ok(())
+        });
+        let written = invoke_and_detect_realloc(&instruction, &account_infos);
+        // Shrinks the account
+        crate::mock::set_mock_handler(|_, account_infos, _| {
+            let mut data = account_infos[0].try_borrow_mut_data()?;
+            let len = data.len() - 3;
+            let shrunk = &mut core::mem::take(&mut *data)[..len];
+            *data = shrunk;
+            Ok(())
+        });
+        let shrunk = invoke_and_detect_realloc(&instruction, &account_infos);
+        // A held mutable borrow is an error, not a panic
+        let guard = account_infos[0].try_borrow_mut_data().unwrap();
+        let borrowed = invoke_and_detect_realloc(&instruction, &account_infos);
+        drop(guard);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(written, Ok(false));
+        assert_eq!(shrunk, Ok(true));
+        assert_eq!(borrowed, Err(ProgramError::AccountBorrowFailed));
+        // Not fired with the borrow held
+        assert_eq!(account_infos[0].data_len(), 5);
+    }
+
+    #[test]
+    fn test_invoke_and_lengths() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [0u64; 2];
+        let mut data = [[0u8; 8]; 2];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(keys[1], false)],
+            data: vec![],
+        };
+
+        // The callee shrinks the account it is passed
+        crate::mock::set_mock_handler(|_, account_infos, _| {
+            let mut data = account_infos[1].try_borrow_mut_data()?;
+            let len = data.len() - 3;
+            let shrunk = &mut core::mem::take(&mut *data)[..len];
+            *data = shrunk;
+            Ok(())
+        });
+        let lengths = invoke_and_lengths(&instruction, &account_infos, &[1, 0, 1]);
+        let out_of_bounds = invoke_and_lengths(&instruction, &account_infos, &[0, 2]);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(lengths, Ok(vec![5, 8, 5]));
+        assert_eq!(out_of_bounds, Err(ProgramError::NotEnoughAccountKeys));
+        // Not fired again
+        assert_eq!(account_infos[1].data_len(), 5);
+    }
+}
@@ -1,10 +1,17 @@
 #![allow(unused)] // unused when target_os is not solana
 
-use std::{marker::PhantomData, mem::ManuallyDrop, ptr::NonNull};
+// Only `core` and `alloc`, so that the layout logic doesn't depend on `std`
+use alloc::borrow::Cow;
+use core::{marker::PhantomData, mem::ManuallyDrop, ptr::NonNull};
+
+use crate::vec_like::VecLike;
 
 use solana_program::{
     instruction::{AccountMeta, Instruction},
-    stable_layout::stable_instruction::StableInstruction,
+    pubkey::Pubkey,
+    stable_layout::{
+        stable_instruction::StableInstruction, stable_vec::StableVec as StableVecUpstream,
+    },
 };
 
 /// This wrapper type with no constructor ensures that no user can
@@ -13,11 +20,25 @@ use solana_program::{
 /// We provide only an immutable borrow method, which ensures that
 /// the inner type is not modified in the absence of unsafe code.
 ///
-/// StableInstruction uses NonNull<T> which is invariant over T.
-/// NonNull<T> is clonable. It's the same type used by Rc<T> and
-/// Arc<T>. It is safe to have an aliasing pointer to the same
+/// StableInstruction uses `NonNull<T>` which is invariant over T.
+/// `NonNull<T>` is clonable. It's the same type used by `Rc<T>` and
+/// `Arc<T>`. It is safe to have an aliasing pointer to the same
 /// allocation as the underlying vectors so long as we perform
 /// no modificiations.
+///
+/// To reuse an instruction across CPIs while tweaking its data in between,
+/// drop the stabilizer, mutate the instruction, and stabilize it again. The
+/// borrow makes mutating the instruction while it is stabilized a compile
+/// error, which is why there is no way to update a live stabilizer in place.
+/// Stabilizing only copies a few words, and as long as the mutation doesn't
+/// reallocate, the new view points at the same buffers. For the same reason
+/// there is no owned stable instruction with a `replace_data`: replacing
+/// `instruction.data` with `clear` and `extend_from_slice` already reuses its
+/// buffer, reallocating only if the new data outgrows its capacity.
+///
+/// Stabilizing never touches the heap: the whole scaffolding is this
+/// stack-allocated view, so a program firing many CPIs pays no per-CPI
+/// allocation here and gains nothing from pooling or arena-allocating it.
 pub struct InstructionStabilizer<'a> {
     /// A stable instruction that will not be dropped. By circumventing the
     /// `Drop` implementation, this becomes a view (similar to a slice)
@@ -39,23 +60,83 @@ pub struct InstructionStabilizer<'a> {
 }
 
 impl<'ix> InstructionStabilizer<'ix> {
+    /// Same as [`stabilize_instruction`].
     #[inline(always)]
-    pub fn stabilize(instruction: &Instruction) -> InstructionStabilizer {
+    pub fn stabilize(instruction: &Instruction) -> InstructionStabilizer<'_> {
         stabilize_instruction(instruction)
     }
 
+    /// Stabilizes the instruction behind a `Cow`, whether borrowed or owned,
+    /// without cloning it. The view lives as long as the borrow of the `Cow`.
+    #[inline(always)]
+    #[allow(clippy::ptr_arg)] // for call sites holding a `Cow`
+    pub fn stabilize_cow(cow: &'ix Cow<'_, Instruction>) -> InstructionStabilizer<'ix> {
+        stabilize_instruction(cow)
+    }
+
+    /// Stabilizes an instruction whose account metas and data live in any
+    /// [`VecLike`] buffers. The view lives as long as the borrowed buffers.
     #[inline(always)]
-    fn new(
-        stabilized_instruction: core::mem::ManuallyDrop<StableInstruction>,
-        // Note: This is where 'ix is inherited
-        _instruction: &'ix Instruction,
+    pub fn stabilize_vec_like<A, D>(
+        program_id: &Pubkey,
+        accounts: &'ix A,
+        data: &'ix D,
+    ) -> InstructionStabilizer<'ix>
+    where
+        A: VecLike<AccountMeta>,
+        D: VecLike<u8>,
+    {
+        Self::from_stable_vecs(
+            *program_id,
+            StableVec::from_vec_like(accounts),
+            StableVec::from_vec_like(data),
+        )
+    }
+
+    /// Stabilizes an instruction from borrowed parts, without requiring an
+    /// `Instruction` (and its two `Vec`s) to exist. The view lives as long
+    /// as the borrowed account metas and data.
+    #[inline(always)]
+    pub fn stabilize_parts(
+        program_id: &Pubkey,
+        accounts: &'ix [AccountMeta],
+        data: &'ix [u8],
+    ) -> InstructionStabilizer<'ix> {
+        Self::from_stable_vecs(
+            *program_id,
+            StableVec::from_slice(accounts),
+            StableVec::from_slice(data),
+        )
+    }
+
+    /// The only place views are turned into upstream `StableVec`s. The
+    /// caller must not let the view outlive the buffers of `accounts` and
+    /// `data`, which 'ix must be tied to.
+    #[inline(always)]
+    fn from_stable_vecs(
+        program_id: Pubkey,
+        accounts: StableVec<AccountMeta>,
+        data: StableVec<u8>,
     ) -> InstructionStabilizer<'ix> {
         Self {
-            stabilized_instruction,
-            phantom_instruction: PhantomData::<&'ix Instruction>,
+            stabilized_instruction: ManuallyDrop::new(StableInstruction {
+                // SAFETY: transmuting between identical repr(C) structs (see
+                // `StableVec`). The upstream `StableVec` frees its buffer on
+                // drop, which `ManuallyDrop` prevents, so it stays a view.
+                accounts: unsafe {
+                    core::mem::transmute::<StableVec<AccountMeta>, StableVecUpstream<AccountMeta>>(
+                        accounts,
+                    )
+                },
+                data: unsafe { core::mem::transmute::<StableVec<u8>, StableVecUpstream<u8>>(data) },
+                program_id,
+            }),
+            phantom_instruction: PhantomData,
         }
     }
 
+    /// The stabilized instruction, e.g. to inspect or serialize it with the
+    /// exact layout handed to the syscall.
     #[inline(always)]
     pub fn stable_instruction_ref<'borrow>(&'borrow self) -> &'borrow StableInstruction
     where
@@ -65,63 +146,371 @@ impl<'ix> InstructionStabilizer<'ix> {
         &self.stabilized_instruction
     }
 
+    /// The address of the stabilized instruction, as passed to
+    /// `sol_invoke_signed_rust`.
     #[inline(always)]
     pub fn instruction_addr(&self) -> *const u8 {
         self.stable_instruction_ref() as *const StableInstruction as *const u8
     }
 }
 
+/// Renders the raw bytes handed to the syscall in hex, for debugging layout
+/// mismatches (e.g. after bumping `solana-program`), one line each for
+///
+/// - `header`: the `StableInstruction` itself (both `StableVec`s, then the
+///   program id),
+/// - `accounts`: the account metas it points to,
+/// - `data`: the data it points to.
+///
+/// Off-chain only.
+#[cfg(not(target_os = "solana"))]
+pub fn dump_stable_hex(stabilizer: &InstructionStabilizer) -> String {
+    use alloc::{format, string::String};
+    use core::fmt::Write;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().fold(String::new(), |mut hex, byte| {
+            let _ = write!(hex, "{byte:02x}");
+            hex
+        })
+    }
+
+    let stable = stabilizer.stable_instruction_ref();
+    // SAFETY: both views cover initialized memory: the `StableInstruction`
+    // has no padding, and `AccountMeta` (34 bytes, align 1) neither
+    let header = unsafe {
+        core::slice::from_raw_parts(
+            stabilizer.instruction_addr(),
+            core::mem::size_of::<StableInstruction>(),
+        )
+    };
+    let accounts = unsafe {
+        core::slice::from_raw_parts(
+            stable.accounts.as_ptr() as *const u8,
+            core::mem::size_of_val::<[AccountMeta]>(&stable.accounts),
+        )
+    };
+
+    format!(
+        "header: {}\naccounts: {}\ndata: {}",
+        hex(header),
+        hex(accounts),
+        hex(&stable.data)
+    )
+}
+
+/// Copies a stabilized instruction back into an owned `Instruction`.
+pub(crate) fn to_instruction(stable: &StableInstruction) -> Instruction {
+    Instruction {
+        program_id: stable.program_id,
+        accounts: stable.accounts.to_vec(),
+        data: stable.data.to_vec(),
+    }
+}
+
+/// Same layout as the upstream `StableVec`, whose fields are all `u64`, on
+/// every target. The transmutes between the two are by value: `cap` and
+/// `len` are `u64`s, and `ptr` is widened to 64 bits on 32-bit hosts (e.g.
+/// `wasm32-wasip1`) by zeroed high bytes placed according to the host's byte
+/// order, so the view is correct on big-endian hosts too.
 #[repr(C)]
 pub struct StableVec<T> {
+    #[cfg(target_endian = "big")]
+    _ptr_high: [u8; PTR_PADDING],
     pub ptr: NonNull<T>,
-    pub cap: usize,
-    pub len: usize,
+    #[cfg(target_endian = "little")]
+    _ptr_high: [u8; PTR_PADDING],
+    pub cap: u64,
+    pub len: u64,
     _marker: PhantomData<T>,
 }
 
+/// Bytes widening a pointer to the upstream `u64`: none on 64-bit targets.
+/// They are a field rather than implicit padding so that the high bytes of
+/// the upstream `addr` are initialized zeros.
+const PTR_PADDING: usize = 8 - core::mem::size_of::<usize>();
 
-#[inline(always)] // only one call site (wrapper fn) so inline there
-fn stabilize_instruction<'ix_ref>(
-    ix: &'ix_ref Instruction,
-) -> InstructionStabilizer<'ix_ref> {
-    // Get StableVec out of instruction data Vec<u8>
-    let data: StableVec<u8> = {
-        // Get vector parts
-        let ptr = NonNull::new(ix.data.as_ptr() as *mut u8).expect("vector ptr should be valid");
-        let len = ix.data.len();
-        let cap = ix.data.capacity();
+// The transmutes of `InstructionStabilizer::from_stable_vecs`, and
+// `StaticInstruction` passed as a `StableInstruction`, rely on these
+const _: () = {
+    use core::mem::{align_of, size_of};
+    assert!(
+        size_of::<NonNull<u8>>() + PTR_PADDING == size_of::<u64>(),
+        "`StableVec::ptr` must be widened to the upstream `u64` address"
+    );
+    assert!(
+        size_of::<StableVec<AccountMeta>>() == size_of::<StableVecUpstream<AccountMeta>>()
+            && size_of::<StableVec<u8>>() == size_of::<StableVecUpstream<u8>>(),
+        "`StableVec` must have the size of the upstream `StableVec` on every target"
+    );
+    assert!(
+        align_of::<StableVec<AccountMeta>>() == align_of::<StableVecUpstream<AccountMeta>>()
+            && align_of::<StableVec<u8>>() == align_of::<StableVecUpstream<u8>>(),
+        "`StableVec` must have the alignment of the upstream `StableVec` on every target"
+    );
+};
 
+impl<T> StableVec<T> {
+    /// A view into a borrowed slice, with `cap == len`. The caller must not
+    /// let the view outlive the slice.
+    #[inline(always)]
+    pub fn from_slice(slice: &[T]) -> StableVec<T> {
         StableVec {
-            ptr,
-            cap,
-            len,
-            _marker: std::marker::PhantomData,
+            _ptr_high: [0; PTR_PADDING],
+            ptr: NonNull::from(slice).cast::<T>(),
+            cap: slice.len() as u64,
+            len: slice.len() as u64,
+            _marker: PhantomData,
         }
-    };
+    }
 
-    // Get StableVec out of instruction accounts Vec<Accountmeta>
-    let accounts: StableVec<AccountMeta> = {
-        // Get vector parts
-        let ptr = NonNull::new(ix.accounts.as_ptr() as *mut AccountMeta)
-            .expect("vector ptr should be valid");
-        let len = ix.accounts.len();
-        let cap = ix.accounts.capacity();
+    /// A view into a [`VecLike`] buffer, keeping its capacity. The caller
+    /// must not let the view outlive the borrow of the buffer.
+    #[inline(always)]
+    pub fn from_vec_like(buffer: &impl VecLike<T>) -> StableVec<T> {
+        StableVec {
+            _ptr_high: [0; PTR_PADDING],
+            // SAFETY: `VecLike` guarantees a non-null pointer
+            ptr: unsafe { NonNull::new_unchecked(buffer.as_ptr() as *mut T) },
+            cap: buffer.capacity() as u64,
+            len: buffer.len() as u64,
+            _marker: PhantomData,
+        }
+    }
 
+    /// A view into a `'static` slice. Since this type has no `Drop`
+    /// implementation, the slice is never freed, so this is usable in
+    /// `const` contexts.
+    #[inline(always)]
+    pub const fn from_static(slice: &'static [T]) -> StableVec<T> {
         StableVec {
-            ptr,
-            cap,
-            len,
-            _marker: std::marker::PhantomData,
+            _ptr_high: [0; PTR_PADDING],
+            // SAFETY: slices are never null, even when empty
+            ptr: unsafe { NonNull::new_unchecked(slice.as_ptr() as *mut T) },
+            cap: slice.len() as u64,
+            len: slice.len() as u64,
+            _marker: PhantomData,
         }
-    };
+    }
+}
 
-    InstructionStabilizer::<'ix_ref>::new(
-        ManuallyDrop::new(StableInstruction {
-            // Transmuting between identical repr(C) structs
-            accounts: unsafe { core::mem::transmute(accounts) },
-            data: unsafe { core::mem::transmute(data) },
-            program_id: ix.program_id,
-        }),
-        ix,
+/// Views an instruction as the `StableInstruction` the invoke syscall
+/// expects, without copying or allocating. The view borrows the instruction,
+/// so it can't be mutated or dropped while the view is alive.
+///
+/// This is the layout logic of every invoke function of this crate, usable
+/// on its own, e.g. by other CPI implementations:
+///
+/// ```
+/// use solana_invoke::stabilize_instruction;
+/// use solana_program::{instruction::Instruction, pubkey::Pubkey};
+///
+/// let instruction = Instruction {
+///     program_id: Pubkey::new_unique(),
+///     accounts: vec![],
+///     data: vec![1, 2, 3],
+/// };
+/// let stabilizer = stabilize_instruction(&instruction);
+/// let stable = stabilizer.stable_instruction_ref();
+/// assert_eq!(stable.data.as_ptr(), instruction.data.as_ptr());
+/// ```
+#[inline(always)]
+pub fn stabilize_instruction(ix: &Instruction) -> InstructionStabilizer<'_> {
+    // Get StableVecs out of the instruction's Vecs. Vec pointers are never
+    // null (empty vectors are dangling but aligned), so there is nothing to
+    // check or panic on here
+    InstructionStabilizer::from_stable_vecs(
+        ix.program_id,
+        StableVec::from_vec_like(&ix.accounts),
+        StableVec::from_vec_like(&ix.data),
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stabilize_empty_instruction() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+        let stabilizer = InstructionStabilizer::stabilize(&instruction);
+        let stable = stabilizer.stable_instruction_ref();
+        assert_eq!(stable.program_id, instruction.program_id);
+        assert!(stable.accounts.is_empty());
+        assert!(stable.data.is_empty());
+    }
+
+    /// Stabilizing must never copy the account metas or the data: the
+    /// syscall is handed the original buffers, which is the point of the
+    /// crate
+    #[test]
+    fn test_stabilize_is_zero_copy() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(Pubkey::new_unique(), true),
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            ],
+            data: vec![7; 100],
+        };
+        let accounts_ptr = instruction.accounts.as_ptr();
+        let data_ptr = instruction.data.as_ptr();
+
+        let assert_zero_copy = |stabilizer: &InstructionStabilizer| {
+            // Read back through the address passed to the syscall
+            let stable = unsafe { &*(stabilizer.instruction_addr() as *const StableInstruction) };
+            assert_eq!(stable.accounts.as_ptr(), accounts_ptr);
+            assert_eq!(stable.data.as_ptr(), data_ptr);
+            assert_eq!(stable.accounts.len(), 2);
+            assert_eq!(stable.data.len(), 100);
+        };
+
+        assert_zero_copy(&InstructionStabilizer::stabilize(&instruction));
+        assert_zero_copy(&InstructionStabilizer::stabilize_parts(
+            &instruction.program_id,
+            &instruction.accounts,
+            &instruction.data,
+        ));
+        assert_zero_copy(&InstructionStabilizer::stabilize_vec_like(
+            &instruction.program_id,
+            &instruction.accounts,
+            &instruction.data,
+        ));
+        assert_zero_copy(&InstructionStabilizer::stabilize_cow(&Cow::Borrowed(
+            &instruction,
+        )));
+    }
+
+    #[test]
+    fn test_stabilize_cow() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(Pubkey::new_unique(), false)],
+            data: vec![1, 2, 3],
+        };
+
+        let borrowed = Cow::Borrowed(&instruction);
+        let stabilizer = InstructionStabilizer::stabilize_cow(&borrowed);
+        let stable = stabilizer.stable_instruction_ref();
+        // A view into the original buffers
+        assert_eq!(stable.data.as_ptr(), instruction.data.as_ptr());
+        assert_eq!(stable.accounts.as_ptr(), instruction.accounts.as_ptr());
+
+        let mut owned: Cow<Instruction> = Cow::Borrowed(&instruction);
+        owned.to_mut().data.push(4);
+        let stabilizer = InstructionStabilizer::stabilize_cow(&owned);
+        let stable = stabilizer.stable_instruction_ref();
+        assert_eq!(stable.program_id, instruction.program_id);
+        assert_eq!(&stable.data[..], &[1, 2, 3, 4]);
+        assert_eq!(stable.data.as_ptr(), owned.data.as_ptr());
+        assert_eq!(&stable.accounts[..], &instruction.accounts[..]);
+    }
+
+    #[test]
+    fn test_stable_vec_values() {
+        let mut data = Vec::with_capacity(64);
+        data.extend_from_slice(&[1, 2, 3]);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(Pubkey::new_unique(), true)],
+            data,
+        };
+        let stabilizer = InstructionStabilizer::stabilize(&instruction);
+        let stable = stabilizer.stable_instruction_ref();
+
+        // The upstream `u64` fields hold the native values regardless of the
+        // host's byte order
+        assert_eq!(stable.data.addr, instruction.data.as_ptr() as u64);
+        assert_eq!(stable.data.cap, 64);
+        assert_eq!(stable.data.len, 3);
+        assert_eq!(stable.accounts.addr, instruction.accounts.as_ptr() as u64);
+        assert_eq!(stable.accounts.len, 1);
+        assert_eq!(&stable.accounts[..], &instruction.accounts[..]);
+    }
+
+    #[test]
+    fn test_dump_stable_hex() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_from_array([0xab; 32]),
+            accounts: vec![
+                AccountMeta::new(Pubkey::new_unique(), true),
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            ],
+            data: vec![0xde, 0xad, 0xbe, 0xef],
+        };
+        let stabilizer = InstructionStabilizer::stabilize(&instruction);
+        let dump = dump_stable_hex(&stabilizer);
+
+        let lines: Vec<&str> = dump.lines().collect();
+        let [header, accounts, data] = lines[..] else {
+            panic!("unexpected dump: {dump}");
+        };
+        let header = header.strip_prefix("header: ").unwrap();
+        assert_eq!(header.len(), 2 * 80);
+        assert_eq!(header.len(), 2 * core::mem::size_of::<StableInstruction>());
+        assert!(header.ends_with(&"ab".repeat(32)));
+        let accounts = accounts.strip_prefix("accounts: ").unwrap();
+        assert_eq!(accounts.len(), 2 * 2 * 34);
+        assert!(accounts.starts_with(&hex_of(instruction.accounts[0].pubkey.as_ref())));
+        assert_eq!(&accounts[64..68], "0101");
+        assert_eq!(&accounts[132..136], "0000");
+        assert_eq!(data, "data: deadbeef");
+
+        fn hex_of(bytes: &[u8]) -> String {
+            bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+        }
+    }
+
+    #[test]
+    fn test_restabilize_after_mutation() {
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(Pubkey::new_unique(), false)],
+            data: vec![0; 8],
+        };
+        let data_ptr = instruction.data.as_ptr();
+
+        for counter in 0..3u64 {
+            {
+                let stabilizer = InstructionStabilizer::stabilize(&instruction);
+                let stable = stabilizer.stable_instruction_ref();
+                assert_eq!(&stable.data[..], &counter.to_le_bytes());
+                assert_eq!(stable.data.as_ptr(), data_ptr);
+            }
+
+            // Only possible once the stabilizer is gone
+            instruction
+                .data
+                .copy_from_slice(&(counter + 1).to_le_bytes());
+        }
+        assert_eq!(instruction.data.as_ptr(), data_ptr);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_fire_with_replaced_data() {
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: Vec::with_capacity(16),
+        };
+        let data_ptr = instruction.data.as_ptr();
+
+        crate::mock::record_invocations();
+        for data in [&[1u8, 2, 3][..], &[4; 16]] {
+            instruction.data.clear();
+            instruction.data.extend_from_slice(data);
+            assert_eq!(crate::invoke(&instruction, &[]), Ok(()));
+        }
+        let fired: Vec<_> = crate::mock::take_invocations()
+            .into_iter()
+            .map(|instruction| instruction.data)
+            .collect();
+        assert_eq!(fired, [vec![1, 2, 3], vec![4; 16]]);
+        assert_eq!(instruction.data.as_ptr(), data_ptr);
+    }
+}
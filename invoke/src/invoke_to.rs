@@ -0,0 +1,90 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta, pubkey::Pubkey,
+};
+
+/// A program whose id is known at compile time, for [`invoke_to`].
+///
+/// `Pubkey` can't be a const generic parameter, so the id is carried by a
+/// marker type instead. Implement it for the programs a hot path CPIs into.
+pub trait KnownProgram {
+    const ID: Pubkey;
+}
+
+/// The system program, see [`SYSTEM_PROGRAM_ID`](crate::SYSTEM_PROGRAM_ID).
+pub struct SystemProgram;
+
+impl KnownProgram for SystemProgram {
+    const ID: Pubkey = crate::SYSTEM_PROGRAM_ID;
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), for an instruction to
+/// `P` given as its account metas and data.
+///
+/// The program id is a constant instead of a field of an `Instruction`, so
+/// no `Instruction` (and none of its `Vec`s) needs to be built, and the id
+/// can't be swapped for another program's at runtime.
+///
+/// ```no_run
+/// use solana_invoke::{invoke_to, SystemProgram};
+/// use solana_program::{account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta};
+///
+/// fn pay(accounts: &[AccountInfo], lamports: u64) -> ProgramResult {
+///     let (payer, recipient) = (&accounts[0], &accounts[1]);
+///     let metas = [
+///         AccountMeta::new(*payer.key, true),
+///         AccountMeta::new(*recipient.key, false),
+///     ];
+///     // `SystemInstruction::Transfer`
+///     let mut data = [0u8; 12];
+///     data[0] = 2;
+///     data[4..].copy_from_slice(&lamports.to_le_bytes());
+///
+///     invoke_to::<SystemProgram>(&metas, &data, &accounts[..2], &[])
+/// }
+/// ```
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_to<P: KnownProgram>(
+    account_metas: &[AccountMeta],
+    data: &[u8],
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    crate::invoke_reusing(&P::ID, account_metas, data, account_infos, signers_seeds)
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoke_to_system_transfer() {
+        let owner = solana_program::system_program::ID;
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [10u64, 0];
+        let mut data = [[0u8; 0]; 2];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let transfer = solana_program::system_instruction::transfer(&keys[0], &keys[1], 3);
+
+        // The callee receives the same instruction as with `transfer`
+        crate::mock::set_mock_handler(|instruction, account_infos, _| {
+            let expected = solana_program::system_instruction::transfer(
+                account_infos[0].key,
+                account_infos[1].key,
+                3,
+            );
+            assert_eq!(*instruction, expected);
+            **account_infos[0].try_borrow_mut_lamports()? -= 3;
+            **account_infos[1].try_borrow_mut_lamports()? += 3;
+            Ok(())
+        });
+        let result =
+            invoke_to::<SystemProgram>(&transfer.accounts, &transfer.data, &account_infos, &[]);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(account_infos[0].lamports(), 7);
+        assert_eq!(account_infos[1].lamports(), 3);
+    }
+}
@@ -1,72 +1,2879 @@
 #![doc = include_str!("../README.md")]
 
+extern crate alloc;
+
+use core::mem::MaybeUninit;
+
 use solana_program::{
-    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
 };
 
+mod account_infos_buffer;
+mod c_abi;
+mod chained;
+mod checks;
+mod compact;
+#[cfg(feature = "depth-guard")]
+pub mod depth_guard;
+#[cfg(feature = "hooks")]
+pub mod hooks;
+mod inspect;
 mod instruction_stabilizer;
+mod invoke_to;
+mod log;
+mod masks;
+#[cfg(all(feature = "mock", not(target_os = "solana")))]
+pub mod mock;
+mod ordering;
+mod packed_meta;
+mod positions;
+mod preflight;
+#[cfg(feature = "receipts")]
+mod receipt;
+mod return_data;
+mod seed_builder;
+pub mod serialize;
+mod sorted_account_infos;
+mod static_instruction;
+pub mod system;
+mod sysvars;
+#[cfg(test)]
+mod test_utils;
+#[cfg(all(feature = "tracing", not(target_os = "solana")))]
+mod trace;
+mod vec_like;
+
+pub use account_infos_buffer::{invoke_signed_buffer, AccountInfosBuffer};
+pub use c_abi::{invoke_signed_c, C_ABI_STACK_ACCOUNTS};
+pub use chained::{invoke_signed_chained, CHAINED_STACK_ACCOUNTS};
+pub use compact::{invoke_compact, CompactInstruction};
+pub use inspect::{invoke_and_detect_realloc, invoke_and_lengths};
+#[cfg(not(target_os = "solana"))]
+pub use instruction_stabilizer::dump_stable_hex;
+pub use instruction_stabilizer::{stabilize_instruction, InstructionStabilizer, StableVec};
+pub use invoke_to::{invoke_to, KnownProgram, SystemProgram};
+pub use masks::{invoke_with_masks, MASK_STACK_ACCOUNTS};
+pub use ordering::merge_infos;
+pub use packed_meta::{from_packed, PackedAccountMeta};
+pub use positions::{invoke_with_positions, POSITIONS_STACK_ACCOUNTS};
+pub use preflight::{invoke_with_check, NoCheck, PreflightCheck, StandardCheck, StrictCheck};
+#[cfg(feature = "receipts")]
+pub use receipt::{invoke_with_receipt, InvokeReceipt};
+pub use return_data::{
+    get_return_data, get_return_data_fixed, invoke_and_forward_return_data, invoke_capturing,
+    invoke_many_capturing, invoke_then,
+};
+pub use seed_builder::{invoke_signed_with, SeedBuilder, SignerSet};
+pub use sorted_account_infos::SortedAccountInfos;
+pub use static_instruction::{invoke_signed_static, invoke_static, StaticInstruction};
+pub use sysvars::{invoke_with_sysvars, Sysvar};
+pub use vec_like::{invoke_signed_vec_like, VecLike};
+
+// The rest of the `solana_cpi` API, so that swapping the crate is a single
+// import change. These don't involve a CPI and are re-exported as-is, except
+// for `get_return_data`, which also reads the `mock` feature's return data.
+pub use solana_program::program::{set_return_data, MAX_RETURN_DATA};
+
+/// The syscall stubs are global, so tests installing them must not run
+/// concurrently.
+#[cfg(test)]
+static STUBS_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+/// Maximum number of signer seed groups (PDA signers) accepted by the runtime
+/// in a single cross-program invocation.
+pub const MAX_SIGNERS: usize = 16;
+
+/// Maximum number of account metas the runtime accepts in the instruction of
+/// a single cross-program invocation (`u8::MAX` since the CPI size
+/// restriction was loosened).
+pub const MAX_CPI_INSTRUCTION_ACCOUNTS: usize = u8::MAX as usize;
 
+/// Maximum length of the instruction data the runtime accepts in a single
+/// cross-program invocation, through either ABI (10 KiB since the CPI size
+/// restriction was loosened). Checked by [`invoke_signed_c`].
+pub const MAX_CPI_INSTRUCTION_DATA_LEN: usize = 10 * 1024;
+
+/// The system program id, checked by [`invoke_system`].
+pub const SYSTEM_PROGRAM_ID: Pubkey = solana_program::system_program::ID;
+
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
 pub fn invoke(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
     invoke_signed(instruction, account_infos, &[])
 }
 
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
 pub fn invoke_unchecked(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
     invoke_signed_unchecked(instruction, account_infos, &[])
 }
 
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
 pub fn invoke_signed(
     instruction: &Instruction,
     account_infos: &[AccountInfo],
     signers_seeds: &[&[&[u8]]],
 ) -> ProgramResult {
-    // Check that the account RefCells are consistent with the request
-    for account_meta in instruction.accounts.iter() {
-        for account_info in account_infos.iter() {
-            if account_meta.pubkey == *account_info.key {
-                if account_meta.is_writable {
-                    let _ = account_info.try_borrow_mut_lamports()?;
-                    let _ = account_info.try_borrow_mut_data()?;
-                } else {
-                    let _ = account_info.try_borrow_lamports()?;
-                    let _ = account_info.try_borrow_data()?;
-                }
-                break;
-            }
-        }
+    checks::check_accounts_len(&instruction.accounts)?;
+    checks::check_borrows(&instruction.accounts, account_infos)?;
+
+    invoke_signed_unchecked(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but a failed borrow check also returns the
+/// index (into `instruction.accounts`) of the account meta whose borrow
+/// failed, e.g. to report which account is still borrowed without parsing
+/// logs.
+///
+/// Errors that don't come from a single account, i.e. too many account metas
+/// or a failed CPI, are returned with the index `instruction.accounts.len()`,
+/// one past the last account meta.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn try_invoke_signed(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> Result<(), (solana_program::program_error::ProgramError, usize)> {
+    let no_account = |error| (error, instruction.accounts.len());
+    checks::check_accounts_len(&instruction.accounts).map_err(no_account)?;
+    checks::check_borrows_reporting_index(&instruction.accounts, account_infos)?;
+
+    invoke_signed_unchecked(instruction, account_infos, signers_seeds).map_err(no_account)
+}
+
+/// Same as [`invoke_signed`], but returns the raw error code on failure, e.g.
+/// to forward or branch on a callee's custom error code without matching on
+/// [`ProgramError`].
+///
+/// The code is the one the syscall returned, as is: for
+/// [`ProgramError::Custom`]`(code)`, `code` itself, except for
+/// `Custom(0)`, which the runtime returns as `CUSTOM_ZERO` (`1 << 32`) since
+/// 0 is success, and for the builtin errors their shifted codes, including
+/// ones this version of [`ProgramError`] doesn't know. Errors from the
+/// checks before the CPI are converted with `u64::from(error)`. Success is
+/// `Ok(())`.
+///
+/// [`ProgramError`]: solana_program::program_error::ProgramError
+/// [`ProgramError::Custom`]: solana_program::program_error::ProgramError::Custom
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_custom_code(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> Result<(), u64> {
+    checks::check_accounts_len(&instruction.accounts).map_err(u64::from)?;
+    checks::check_borrows(&instruction.accounts, account_infos).map_err(u64::from)?;
+
+    use instruction_stabilizer::InstructionStabilizer;
+    let stabilizer = InstructionStabilizer::stabilize(instruction);
+    let (code, _result) =
+        invoke_signed_raw_code(stabilizer.instruction_addr(), account_infos, signers_seeds);
+    match code {
+        solana_program::entrypoint::SUCCESS => Ok(()),
+        code => Err(code),
     }
+}
+
+/// Same as [`invoke_signed`], but first checks that every signer seed group
+/// (bump included) forms a valid program derived address for `program_id`,
+/// the id of the calling program.
+///
+/// This catches seeds or bumps that cannot possibly sign, returning
+/// [`ProgramError::InvalidSeeds`], instead of failing inside the runtime. It
+/// does not recompute the canonical bump, as `find_program_address` is too
+/// costly. This is a separate function because [`invoke_signed`] does not
+/// know the calling program's id.
+///
+/// [`ProgramError::InvalidSeeds`]: solana_program::program_error::ProgramError::InvalidSeeds
+#[cfg(feature = "verify-seeds")]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_verified(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    program_id: &Pubkey,
+) -> ProgramResult {
+    checks::check_seeds_derive(signers_seeds, program_id)?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but first checks that every signer account meta
+/// will actually be signed for, i.e. that its account info is a signer or
+/// that it is the program derived address of one of the seed groups for
+/// `program_id`, the id of the calling program.
+///
+/// A missing signature otherwise only surfaces as the runtime failing the
+/// CPI with [`ProgramError::MissingRequiredSignature`]. This returns the same
+/// error before firing it. Each seed group costs a `create_program_address`,
+/// which is only paid when some signer account meta is not a signer account
+/// info.
+///
+/// [`ProgramError::MissingRequiredSignature`]: solana_program::program_error::ProgramError::MissingRequiredSignature
+#[cfg(feature = "check-signers")]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_checked_signers(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    program_id: &Pubkey,
+) -> ProgramResult {
+    checks::check_signers(
+        &instruction.accounts,
+        account_infos,
+        signers_seeds,
+        program_id,
+    )?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke`], with the relaxed borrow check of [`invoke_signed_fast`].
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_fast(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    invoke_signed_fast(instruction, account_infos, &[])
+}
+
+/// Same as [`invoke_signed`], but with a cheaper borrow check for readonly
+/// accounts.
+///
+/// Writable accounts are checked exactly as in [`invoke_signed`]. For
+/// readonly accounts, executable accounts (such as the callee program) and
+/// sysvars are not checked at all, and all other accounts only have their
+/// data `RefCell` checked. This saves compute on instructions that pass many
+/// readonly accounts, at the cost of not catching a caller that holds a
+/// mutable borrow on one of the skipped `RefCell`s across the CPI.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_fast(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_accounts_len(&instruction.accounts)?;
+    checks::check_borrows_fast(&instruction.accounts, account_infos)?;
 
     invoke_signed_unchecked(instruction, account_infos, signers_seeds)
 }
 
-pub fn invoke_signed_unchecked(
+/// Same as [`invoke_signed`], taking ownership of an instruction built to be
+/// fired once.
+///
+/// The instruction is stabilized in place, its buffers are handed to the
+/// syscall as they are, and it is dropped once the CPI returns, so it cannot
+/// be mutated or dropped while the stable view of it is alive.
+///
+/// ```no_run
+/// use solana_program::{
+///     account_info::AccountInfo, entrypoint::ProgramResult, system_instruction,
+/// };
+///
+/// fn pay(accounts: &[AccountInfo]) -> ProgramResult {
+///     let (payer, recipient) = (&accounts[0], &accounts[1]);
+///
+///     solana_invoke::invoke_owned(
+///         system_instruction::transfer(payer.key, recipient.key, 1),
+///         &accounts[..2],
+///         &[],
+///     )
+/// }
+/// ```
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_owned(
+    instruction: Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    invoke_signed(&instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], for an instruction given as borrowed parts, so
+/// that constant account metas and data can be reused across CPIs to
+/// different programs without building an `Instruction` for each.
+///
+/// The parts are stabilized where they are, with no allocation or copy.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_reusing(
+    program_id: &Pubkey,
+    account_metas: &[AccountMeta],
+    data: &[u8],
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_accounts_len(account_metas)?;
+    checks::check_borrows(account_metas, account_infos)?;
+
+    invoke_signed_parts_unchecked(
+        program_id,
+        account_metas,
+        data,
+        account_infos,
+        signers_seeds,
+    )
+}
+
+/// Same as [`invoke_reusing`], for streaming a large payload through
+/// repeated CPIs: `data` is a window into a buffer reused across calls, e.g.
+/// `&buffer[offset..offset + CHUNK]`.
+///
+/// The window is stabilized as a slice view (its capacity is its length),
+/// so firing it neither allocates nor copies the buffer.
+///
+/// ```no_run
+/// use solana_program::{
+///     account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta,
+///     pubkey::Pubkey,
+/// };
+///
+/// fn stream(
+///     program_id: &Pubkey,
+///     account_metas: &[AccountMeta],
+///     payload: &[u8],
+///     accounts: &[AccountInfo],
+/// ) -> ProgramResult {
+///     for window in payload.chunks(1024) {
+///         solana_invoke::invoke_data_window(program_id, account_metas, window, accounts, &[])?;
+///     }
+///     Ok(())
+/// }
+/// ```
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_data_window(
+    program_id: &Pubkey,
+    account_metas: &[AccountMeta],
+    data: &[u8],
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    invoke_reusing(
+        program_id,
+        account_metas,
+        data,
+        account_infos,
+        signers_seeds,
+    )
+}
+
+/// Same as [`invoke_signed`], for an instruction whose account metas are
+/// synthesized from `account_infos`: one meta per account info, in order,
+/// with its key and its `is_signer` and `is_writable` flags.
+///
+/// This removes the duplication of a separate metas list when the account
+/// infos already carry the flags the callee expects. A PDA isn't a signer in
+/// its account info, so to sign for one with `signers_seeds`, pass a clone of
+/// its account info with `is_signer` set. Seeds only take effect through
+/// signer metas, so if `signers_seeds` isn't empty but no account info is a
+/// signer, this returns [`ProgramError::MissingRequiredSignature`] rather
+/// than silently dropping the signature.
+///
+/// [`ProgramError::MissingRequiredSignature`]: solana_program::program_error::ProgramError::MissingRequiredSignature
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_from_infos(
+    program_id: &Pubkey,
+    account_infos: &[AccountInfo],
+    data: &[u8],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    if !signers_seeds.is_empty() && !account_infos.iter().any(|info| info.is_signer) {
+        return Err(solana_program::program_error::ProgramError::MissingRequiredSignature);
+    }
+    let account_metas: Vec<AccountMeta> = account_infos
+        .iter()
+        .map(|info| AccountMeta {
+            pubkey: *info.key,
+            is_signer: info.is_signer,
+            is_writable: info.is_writable,
+        })
+        .collect();
+
+    invoke_reusing(
+        program_id,
+        &account_metas,
+        data,
+        account_infos,
+        signers_seeds,
+    )
+}
+
+/// Same as [`invoke_signed`] for the common case of exactly one PDA signer.
+///
+/// ```no_run
+/// use solana_program::{
+///     account_info::AccountInfo, entrypoint::ProgramResult, pubkey::Pubkey,
+///     system_instruction,
+/// };
+///
+/// fn withdraw(program_id: &Pubkey, accounts: &[AccountInfo], bump: u8) -> ProgramResult {
+///     let (vault, recipient) = (&accounts[0], &accounts[1]);
+///     let seeds: &[&[u8]] = &[b"vault", &[bump]];
+///     debug_assert_eq!(
+///         Pubkey::create_program_address(seeds, program_id).as_ref(),
+///         Ok(vault.key)
+///     );
+///
+///     solana_invoke::invoke_signed_single(
+///         &system_instruction::transfer(vault.key, recipient.key, 1),
+///         &accounts[..2],
+///         seeds,
+///     )
+/// }
+/// ```
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_single(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    seeds: &[&[u8]],
+) -> ProgramResult {
+    invoke_signed(instruction, account_infos, &[seeds])
+}
+
+/// Same as [`invoke_signed_single`], with `bump` appended to `seeds` as the
+/// last seed.
+///
+/// The seed group is assembled in a stack array of `MAX_SEEDS` seeds, so no
+/// heap allocation is involved. Returns
+/// [`ProgramError::MaxSeedLengthExceeded`] if `seeds` and the bump together
+/// exceed `MAX_SEEDS`, instead of truncating them.
+///
+/// [`ProgramError::MaxSeedLengthExceeded`]: solana_program::program_error::ProgramError::MaxSeedLengthExceeded
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_with_bump(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    seeds: &[&[u8]],
+    bump: u8,
+) -> ProgramResult {
+    let bump = [bump];
+    let mut buffer: [&[u8]; solana_program::pubkey::MAX_SEEDS] =
+        [&[]; solana_program::pubkey::MAX_SEEDS];
+    let seeds = seeds_with_bump(seeds, &bump, &mut buffer)?;
+
+    invoke_signed_single(instruction, account_infos, seeds)
+}
+
+/// Same as [`invoke_signed_with_bump`], but first checks that `seeds` and
+/// `bump` derive `pda_account` for `program_id`, the id of the calling
+/// program, returning [`ProgramError::InvalidSeeds`] otherwise.
+///
+/// This catches signing with the seeds of another account, which the
+/// runtime would only report as a missing signature inside the callee, if
+/// at all. Deriving the address costs a `create_program_address` (about
+/// 1500 compute units), and like `invoke_signed_verified` this needs the
+/// calling program's id, which [`invoke_signed`] does not know.
+///
+/// [`ProgramError::InvalidSeeds`]: solana_program::program_error::ProgramError::InvalidSeeds
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_as(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    pda_account: &AccountInfo,
+    seeds: &[&[u8]],
+    bump: u8,
+    program_id: &Pubkey,
+) -> ProgramResult {
+    let bump = [bump];
+    let mut buffer: [&[u8]; solana_program::pubkey::MAX_SEEDS] =
+        [&[]; solana_program::pubkey::MAX_SEEDS];
+    let seeds = seeds_with_bump(seeds, &bump, &mut buffer)?;
+    match Pubkey::create_program_address(seeds, program_id) {
+        Ok(pda) if pda == *pda_account.key => {}
+        _ => return Err(solana_program::program_error::ProgramError::InvalidSeeds),
+    }
+
+    invoke_signed_single(instruction, account_infos, seeds)
+}
+
+/// Copies `seeds` followed by `bump` into `buffer`, returning the filled
+/// prefix, or [`ProgramError::MaxSeedLengthExceeded`] if they don't fit.
+///
+/// [`ProgramError::MaxSeedLengthExceeded`]: solana_program::program_error::ProgramError::MaxSeedLengthExceeded
+#[inline(always)]
+fn seeds_with_bump<'a, 'b>(
+    seeds: &[&'a [u8]],
+    bump: &'a [u8; 1],
+    buffer: &'b mut [&'a [u8]],
+) -> Result<&'b [&'a [u8]], solana_program::program_error::ProgramError> {
+    let len = seeds.len() + 1;
+    if len > buffer.len() {
+        return Err(solana_program::program_error::ProgramError::MaxSeedLengthExceeded);
+    }
+    buffer[..seeds.len()].copy_from_slice(seeds);
+    buffer[seeds.len()] = bump;
+    Ok(&buffer[..len])
+}
+
+/// Same as [`invoke`], but first reorders `account_infos` to match the order
+/// of `instruction.accounts`, so callers don't have to pass them in the
+/// instruction's order.
+///
+/// Returns [`ProgramError::NotEnoughAccountKeys`] if an account meta has no
+/// matching account info. This clones the account infos into a new `Vec`
+/// (an `Rc` bump per account), so prefer [`invoke`] when the order is already
+/// correct.
+///
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_ordered(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+    let account_infos = ordering::order_account_infos(instruction, account_infos)?;
+    invoke(instruction, &account_infos)
+}
+
+/// Same as [`invoke_signed`] for system program instructions (e.g.
+/// `transfer` or `create_account`), but first checks that the instruction
+/// targets [`SYSTEM_PROGRAM_ID`], returning
+/// [`ProgramError::IncorrectProgramId`] otherwise.
+///
+/// This stops an attacker from substituting a fake system program when the
+/// instruction is built from a system program account passed in by the
+/// caller, at the cost of one 32-byte comparison.
+///
+/// [`ProgramError::IncorrectProgramId`]: solana_program::program_error::ProgramError::IncorrectProgramId
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_system(
     instruction: &Instruction,
     account_infos: &[AccountInfo],
     signers_seeds: &[&[&[u8]]],
 ) -> ProgramResult {
-    #[cfg(target_os = "solana")]
+    checks::check_program_id(instruction, &SYSTEM_PROGRAM_ID)?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but first checks that the instruction targets
+/// one of the `allowed` programs, returning
+/// [`ProgramError::IncorrectProgramId`] otherwise, for programs that only
+/// ever CPI into a fixed set of trusted programs.
+///
+/// As with [`invoke_system`], this stops a spoofed program id from being
+/// invoked when the instruction is built from accounts passed in by the
+/// caller. The whitelist is scanned linearly, which is the fastest lookup
+/// for the few programs it usually holds.
+///
+/// [`ProgramError::IncorrectProgramId`]: solana_program::program_error::ProgramError::IncorrectProgramId
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_whitelisted(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    allowed: &[Pubkey],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_program_allowed(instruction, allowed)?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but first checks that the account info at each
+/// `(index, min_len)` of `expected` (indices into `account_infos`) holds at
+/// least `min_len` bytes of data, for callees expecting fixed-size accounts.
+///
+/// Returns [`ProgramError::AccountDataTooSmall`] without firing if one is
+/// too small, rather than letting the callee fail on it, or
+/// [`ProgramError::NotEnoughAccountKeys`] if an index is out of bounds.
+///
+/// [`ProgramError::AccountDataTooSmall`]: solana_program::program_error::ProgramError::AccountDataTooSmall
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_checked_sizes(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    expected: &[(usize, usize)],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_data_lens(account_infos, expected)?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but with the signer seed groups sorted by the
+/// PDA each derives for `program_id`, the id of the calling program, for
+/// protocols asserting a canonical signer order.
+///
+/// Whole groups are moved, so every PDA is still signed for by its own seeds.
+/// Returns [`ProgramError::InvalidSeeds`] without firing if a group doesn't
+/// derive a PDA. Deriving costs one `create_program_address` per group, and
+/// the sorted groups are collected into a new `Vec`.
+///
+/// [`ProgramError::InvalidSeeds`]: solana_program::program_error::ProgramError::InvalidSeeds
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_sorted_seeds(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    program_id: &Pubkey,
+) -> ProgramResult {
+    let signers_seeds = ordering::sort_signers_seeds(signers_seeds, program_id)?;
+
+    invoke_signed(instruction, account_infos, &signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but with the instruction's account metas sorted
+/// by pubkey, and the account infos selected in that order, for protocols
+/// that require a canonical account order.
+///
+/// Each account meta keeps its signer and writable flags, and duplicates keep
+/// their relative order. Returns [`ProgramError::NotEnoughAccountKeys`] if an
+/// account meta has no matching account info. The sorted account metas and
+/// infos are cloned into new `Vec`s, while the data is passed as is.
+///
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_sorted(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let (account_metas, account_infos) = ordering::sort_account_metas(instruction, account_infos)?;
+    checks::check_accounts_len(&account_metas)?;
+    checks::check_borrows(&account_metas, &account_infos)?;
+
+    invoke_signed_parts_unchecked(
+        &instruction.program_id,
+        &account_metas,
+        &instruction.data,
+        &account_infos,
+        signers_seeds,
+    )
+}
+
+/// Same as [`invoke_ordered`], with signer seeds: selects the account infos
+/// referenced by `instruction.accounts` from `pool`, a superset in any order
+/// (e.g. all the accounts of the calling instruction), and passes them in the
+/// instruction's order.
+///
+/// Returns [`ProgramError::NotEnoughAccountKeys`] if an account meta has no
+/// matching account info in `pool`.
+///
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_auto_order(
+    instruction: &Instruction,
+    pool: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let account_infos = ordering::order_account_infos(instruction, pool)?;
+    invoke_signed(instruction, &account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but first checks that no two account infos
+/// share a key, returning [`ProgramError::InvalidArgument`] otherwise.
+///
+/// This protects CPIs that don't expect an account to be aliased by several
+/// account infos. Every pair of keys is compared, so the check costs
+/// `n * (n - 1) / 2` key comparisons for `n` account infos, which adds up
+/// quickly beyond a few dozen accounts.
+///
+/// [`ProgramError::InvalidArgument`]: solana_program::program_error::ProgramError::InvalidArgument
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_unique(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_unique_keys(account_infos)?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but first checks that each account info at the
+/// given index in `account_infos` is owned by the paired program id, e.g.
+/// that token accounts are owned by the token program.
+///
+/// Returns [`ProgramError::IllegalOwner`] on the first mismatch, and
+/// [`ProgramError::NotEnoughAccountKeys`] if an index is out of bounds.
+/// Owners are read before the CPI, so they reflect any `assign` the caller
+/// performed earlier in the instruction.
+///
+/// [`ProgramError::IllegalOwner`]: solana_program::program_error::ProgramError::IllegalOwner
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_checked_owners(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    expected: &[(usize, Pubkey)],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_owners(account_infos, expected)?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but first checks that the account info of
+/// `instruction.program_id` is executable, returning
+/// [`ProgramError::InvalidAccountData`] otherwise.
+///
+/// Invoking a non-executable account always fails, with a runtime error that
+/// doesn't point at the misconfigured program account. The program account
+/// must be passed in `account_infos`: if it is missing,
+/// [`ProgramError::NotEnoughAccountKeys`] is returned.
+///
+/// [`ProgramError::InvalidAccountData`]: solana_program::program_error::ProgramError::InvalidAccountData
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_checked_executable(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_executable(&instruction.program_id, account_infos)?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but first drops `guards`, any borrow guards
+/// (e.g. a `RefMut` of an account's data, or a tuple or `Vec` of them) the
+/// caller still holds on the accounts.
+///
+/// Holding such a guard across the CPI makes the borrow check fail (or, with
+/// the unchecked functions, lets the callee write behind a live borrow).
+/// Taking the guards by value makes dropping them before the CPI explicit,
+/// and the borrow checker then rejects any use of them afterwards.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_releasing<G>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    guards: G,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    drop(guards);
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke`], but first checks that the instruction data starts
+/// with the 8-byte discriminator `expected`, returning
+/// [`ProgramError::InvalidInstructionData`] otherwise.
+///
+/// This guards against building the wrong instruction for a callee whose
+/// instructions are prefixed with a discriminator (e.g. Anchor programs), at
+/// the cost of one 8-byte comparison.
+///
+/// [`ProgramError::InvalidInstructionData`]: solana_program::program_error::ProgramError::InvalidInstructionData
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_expecting_discriminator(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    expected: [u8; 8],
+) -> ProgramResult {
+    checks::check_discriminator(&instruction.data, &expected)?;
+
+    invoke(instruction, account_infos)
+}
+
+/// Same as [`invoke_signed`], but in debug builds asserts that the lamports
+/// of `account_infos[account_index]` changed by exactly `expected_delta`
+/// over a successful CPI.
+///
+/// This catches slippage or unexpected fees during development. In release
+/// builds, nothing is read or compared. In both, an out of bounds
+/// `account_index` returns [`ProgramError::NotEnoughAccountKeys`] without
+/// firing the CPI.
+///
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_expecting_lamport_delta(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    account_index: usize,
+    expected_delta: i64,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let _account_info = account_infos
+        .get(account_index)
+        .ok_or(solana_program::program_error::ProgramError::NotEnoughAccountKeys)?;
+
+    #[cfg(debug_assertions)]
+    let before = **_account_info.try_borrow_lamports()?;
+
+    invoke_signed(instruction, account_infos, signers_seeds)?;
+
+    #[cfg(debug_assertions)]
     {
-        use instruction_stabilizer::InstructionStabilizer;
-        let stabilizer = InstructionStabilizer::stabilize(instruction);
-        let instruction_addr = stabilizer.instruction_addr();
+        let after = **_account_info.try_borrow_lamports()?;
+        assert_eq!(
+            after as i128 - before as i128,
+            expected_delta as i128,
+            "unexpected lamport delta for account {account_index} ({before} -> {after})"
+        );
+    }
+    #[cfg(not(debug_assertions))]
+    let _ = expected_delta;
 
-        let result = unsafe {
-            solana_program::syscalls::sol_invoke_signed_rust(
-                instruction_addr,
-                account_infos as *const _ as *const u8,
-                account_infos.len() as u64,
-                signers_seeds as *const _ as *const u8,
-                signers_seeds.len() as u64,
-            )
-        };
-        match result {
-            solana_program::entrypoint::SUCCESS => Ok(()),
-            _ => Err(result.into()),
-        }
+    Ok(())
+}
+
+/// Same as [`invoke_signed`], with exactly one account info per account meta
+/// of the instruction, as a fixed-size array.
+///
+/// Tying the array size to the instruction's shape catches call sites whose
+/// account infos drifted from what the instruction expects: debug builds
+/// assert that `instruction.accounts.len() == N`. In release builds nothing
+/// is compared and this is plain [`invoke_signed`].
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_exact<const N: usize>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo; N],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    debug_assert_eq!(
+        instruction.accounts.len(),
+        N,
+        "instruction has {} account metas, but {N} account infos were passed",
+        instruction.accounts.len()
+    );
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], then unpacks the data of `target`, e.g. a token
+/// account whose balance the CPI changed.
+///
+/// `target` is only borrowed once the CPI has returned, so the data is the
+/// state the callee left. Returns [`ProgramError::AccountBorrowFailed`] if
+/// the data is still borrowed, and the error of [`Pack::unpack`] if it
+/// doesn't hold an initialized `T`.
+///
+/// [`ProgramError::AccountBorrowFailed`]: solana_program::program_error::ProgramError::AccountBorrowFailed
+/// [`Pack::unpack`]: solana_program::program_pack::Pack::unpack
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_and_load<T>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    target: &AccountInfo,
+    signers_seeds: &[&[&[u8]]],
+) -> Result<T, solana_program::program_error::ProgramError>
+where
+    T: solana_program::program_pack::Pack + solana_program::program_pack::IsInitialized,
+{
+    invoke_signed(instruction, account_infos, signers_seeds)?;
+
+    let data = target.try_borrow_data()?;
+    T::unpack(&data)
+}
+
+/// Same as [`invoke`] for each instruction in order, firing all of them
+/// regardless of individual failures and returning one result per
+/// instruction. Each failure is logged with its index.
+///
+/// On-chain, a callee that fails aborts the whole transaction instead of
+/// returning an error to the caller, so the errors that can be skipped over
+/// are those of this crate's pre-flight checks (e.g. a borrow failure on an
+/// account of one instruction), which are returned before firing that CPI.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_many_best_effort(
+    instructions: &[Instruction],
+    account_infos: &[AccountInfo],
+) -> Vec<ProgramResult> {
+    instructions
+        .iter()
+        .enumerate()
+        .map(|(index, instruction)| {
+            let result = invoke(instruction, account_infos);
+            if let Err(error) = &result {
+                log::log(&format!("invoke {index} failed: {error}"));
+            }
+            result
+        })
+        .collect()
+}
+
+/// Same as [`invoke_signed`], but only fires the CPI if at least `min_cu`
+/// compute units remain, returning `Ok(false)` without firing otherwise.
+///
+/// Running out of compute within a CPI aborts the whole transaction, so
+/// long-running handlers can use this to stop early and commit the work done
+/// so far instead. `min_cu` should cover what the runtime charges for the CPI
+/// (`mock::INVOKE_UNITS` plus one unit per `mock::CPI_BYTES_PER_UNIT` bytes of
+/// data) and the callee's own cost.
+///
+/// Off-chain, the remaining units are the simulated ones of the `mock`
+/// feature when metering, and `solana_program`'s `SyscallStubs` otherwise
+/// (which report 0 by default).
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_if_enough_compute(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    min_cu: u64,
+    signers_seeds: &[&[&[u8]]],
+) -> Result<bool, solana_program::program_error::ProgramError> {
+    if remaining_compute_units() < min_cu {
+        return Ok(false);
     }
+    invoke_signed(instruction, account_infos, signers_seeds)?;
 
-    #[cfg(not(target_os = "solana"))]
-    {
-        core::hint::black_box((instruction, account_infos, signers_seeds));
-        panic!("not supported when target_os != solana");
+    Ok(true)
+}
+
+/// Same as [`invoke_signed`], then adds the compute units consumed by the
+/// CPI to `cu_used`, e.g. to track the total cost of a handler's CPIs.
+///
+/// The compute units are measured with `sol_remaining_compute_units` right
+/// before and after the CPI, so they include what the runtime charges for
+/// it, the callee's own consumption and the cost of one such call. Off-chain,
+/// they are the `mock` feature's simulated units when metering, i.e.
+/// `mock::INVOKE_UNITS` plus one unit per `mock::CPI_BYTES_PER_UNIT` bytes of
+/// data plus what the handler consumes, and 0 otherwise. Nothing is added if
+/// the CPI fails.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_accumulating(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    cu_used: &mut u64,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let before = remaining_compute_units();
+    invoke_signed(instruction, account_infos, signers_seeds)?;
+    *cu_used += before.saturating_sub(remaining_compute_units());
+
+    Ok(())
+}
+
+/// Same as [`invoke_signed`], but treats an instruction with no account
+/// metas and no data as a no-op placeholder, e.g. an empty entry in a
+/// router's plan, and returns `Ok(())` without firing it.
+///
+/// The skip condition is exactly `instruction.accounts.is_empty() &&
+/// instruction.data.is_empty()`: the program id is ignored, and an
+/// instruction with either account metas or data is checked and fired as
+/// with [`invoke_signed`].
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_or_skip(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    if instruction.accounts.is_empty() && instruction.data.is_empty() {
+        return Ok(());
+    }
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed`], but only fires an instruction the first time
+/// it is seen, e.g. to keep a complex flow from firing the same CPI twice.
+///
+/// The instruction is hashed (SHA-256) from its stabilized view, i.e. the
+/// program id, the account metas and the data handed to the syscall, and the
+/// hash is recorded in `seen` once the CPI succeeds. Returns `Ok(false)`
+/// without firing if the hash was already in `seen`.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_once(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    seen: &mut std::collections::HashSet<[u8; 32]>,
+    signers_seeds: &[&[&[u8]]],
+) -> Result<bool, solana_program::program_error::ProgramError> {
+    let hash = stable_hash(instruction);
+    if seen.contains(&hash) {
+        return Ok(false);
+    }
+    invoke_signed(instruction, account_infos, signers_seeds)?;
+    seen.insert(hash);
+
+    Ok(true)
+}
+
+/// SHA-256 of the program id, the number of account metas (as a `u64`, so the
+/// hash is the same on 32 and 64-bit targets), the account metas and the
+/// data of the stabilized instruction.
+fn stable_hash(instruction: &Instruction) -> [u8; 32] {
+    let stabilizer = instruction_stabilizer::InstructionStabilizer::stabilize(instruction);
+    let stable = stabilizer.stable_instruction_ref();
+    // SAFETY: `AccountMeta` is 34 bytes with no padding, so the account metas
+    // are initialized bytes
+    let accounts = unsafe {
+        core::slice::from_raw_parts(
+            stable.accounts.as_ptr() as *const u8,
+            core::mem::size_of_val::<[AccountMeta]>(&stable.accounts),
+        )
+    };
+
+    // The stable view counts in `u64`, whatever the target's `usize`
+    let accounts_len: u64 = stable.accounts.len();
+
+    solana_program::hash::hashv(&[
+        stable.program_id.as_ref(),
+        &accounts_len.to_le_bytes(),
+        accounts,
+        &stable.data,
+    ])
+    .to_bytes()
+}
+
+#[inline(always)]
+pub(crate) fn remaining_compute_units() -> u64 {
+    #[cfg(all(feature = "mock", not(target_os = "solana")))]
+    if let Some(remaining) = mock::remaining_simulated_compute() {
+        return remaining;
+    }
+
+    solana_program::compute_units::sol_remaining_compute_units()
+}
+
+/// Performs every pre-flight check of the checked invoke path without
+/// firing the CPI.
+///
+/// In addition to the `RefCell` borrow check done by [`invoke_signed`], this
+/// verifies that
+///
+/// - every account meta in the instruction has a matching account info,
+///   returning [`ProgramError::NotEnoughAccountKeys`] otherwise,
+/// - no account meta requests write access to an account info that is not
+///   writable, returning [`ProgramError::InvalidArgument`] otherwise,
+/// - the signer seeds respect the runtime's [`MAX_SIGNERS`], `MAX_SEEDS` and
+///   `MAX_SEED_LEN` limits.
+///
+/// Like [`invoke_signed`], it also rejects instructions with more than
+/// [`MAX_CPI_INSTRUCTION_ACCOUNTS`] account metas.
+///
+/// The borrow check is performed even with the `no-borrow-check` feature.
+///
+/// Returns `Ok(())` if the CPI would be well-formed. Since the syscall is
+/// never reached, this also works when `target_os != solana`, which makes it
+/// suitable for unit testing instruction construction.
+///
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+/// [`ProgramError::InvalidArgument`]: solana_program::program_error::ProgramError::InvalidArgument
+pub fn dry_run(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_seeds(signers_seeds)?;
+    checks::check_accounts_len(&instruction.accounts)?;
+    checks::check_accounts(instruction, account_infos)?;
+    // Not `check_borrows`, which the `no-borrow-check` feature compiles out
+    checks::check_borrows_indexed(&instruction.accounts, account_infos).map_err(|(e, _)| e)
+}
+
+/// Invokes over the initialized prefix of a preallocated account info buffer
+/// without copying it into a contiguous `[AccountInfo]` first.
+///
+/// This is intended for frameworks that marshal accounts into a fixed
+/// `[MaybeUninit<AccountInfo>; N]` and only fill part of it. The first
+/// `init_len` elements are reinterpreted in place as `&[AccountInfo]` and
+/// handed to [`invoke_signed`], so the usual borrow checks still apply.
+///
+/// # Panics
+///
+/// Panics if `init_len > infos.len()`.
+///
+/// # Safety
+///
+/// The caller must guarantee that:
+///
+/// - every element of `infos[..init_len]` has been fully initialized with a
+///   valid `AccountInfo` (e.g. via [`MaybeUninit::write`]). Reading an
+///   uninitialized `AccountInfo` is immediate undefined behavior: its `Rc`s
+///   and references would be dereferenced by both the borrow check and the
+///   runtime.
+/// - none of the initialized elements are moved out of, dropped, or
+///   overwritten for the duration of this call. The buffer is only borrowed
+///   here; dropping the initialized elements afterwards remains the caller's
+///   responsibility, as `MaybeUninit` never drops its contents.
+///
+/// `MaybeUninit<T>` is guaranteed to have the same size, alignment and ABI as
+/// `T`, so the initialized prefix has exactly the layout the syscall expects
+/// for an account info slice.
+///
+/// [`MaybeUninit::write`]: core::mem::MaybeUninit::write
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub unsafe fn invoke_signed_maybe_uninit(
+    instruction: &Instruction,
+    infos: &[MaybeUninit<AccountInfo>],
+    init_len: usize,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let initialized = &infos[..init_len];
+    // SAFETY: the caller guarantees the first `init_len` elements are
+    // initialized, and `MaybeUninit<T>` is layout-compatible with `T`.
+    let account_infos = unsafe {
+        core::slice::from_raw_parts(initialized.as_ptr() as *const AccountInfo, init_len)
+    };
+
+    invoke_signed(instruction, account_infos, signers_seeds)
+}
+
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_unchecked(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    use instruction_stabilizer::InstructionStabilizer;
+    let stabilizer = InstructionStabilizer::stabilize(instruction);
+    invoke_signed_raw(stabilizer.instruction_addr(), account_infos, signers_seeds)
+}
+
+/// Same as [`invoke_signed_unchecked`], for an instruction given as borrowed
+/// parts rather than an `Instruction`.
+#[inline(always)]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+pub(crate) fn invoke_signed_parts_unchecked(
+    program_id: &Pubkey,
+    account_metas: &[AccountMeta],
+    data: &[u8],
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    use instruction_stabilizer::InstructionStabilizer;
+    let stabilizer = InstructionStabilizer::stabilize_parts(program_id, account_metas, data);
+    invoke_signed_raw(stabilizer.instruction_addr(), account_infos, signers_seeds)
+}
+
+/// Returns the account infos address and length exactly as they are passed to
+/// `sol_invoke_signed_rust`, for integrators composing their own invoke.
+#[inline(always)]
+pub fn account_infos_ptr(account_infos: &[AccountInfo]) -> (*const u8, u64) {
+    (
+        account_infos.as_ptr() as *const u8,
+        account_infos.len() as u64,
+    )
+}
+
+/// Returns the signer seeds address and length exactly as they are passed to
+/// `sol_invoke_signed_rust` (and `sol_invoke_signed_c`, as a `&[&[u8]]` has
+/// the layout of C's `SolSignerSeeds`), for integrators composing their own
+/// invoke.
+#[inline(always)]
+pub fn seeds_ptr(signers_seeds: &[&[&[u8]]]) -> (*const u8, u64) {
+    (
+        signers_seeds.as_ptr() as *const u8,
+        signers_seeds.len() as u64,
+    )
+}
+
+/// Fires `sol_invoke_signed_rust` with a pointer to a `StableInstruction`
+/// (or a type with the identical layout).
+/// On the host, this calls the invoke hook and the mock handler when the
+/// `hooks` and `mock` features are enabled, and otherwise panics. Callers up
+/// to the public invoke functions are `#[track_caller]` there, so the panic
+/// points at the user's call site.
+#[inline(always)]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+fn invoke_signed_raw(
+    instruction_addr: *const u8,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    invoke_signed_raw_code(instruction_addr, account_infos, signers_seeds).1
+}
+
+/// Same as [`invoke_signed_raw`], but also returns the raw code the syscall
+/// returned (or the code of the `depth-guard` error, if it didn't fire).
+#[inline(always)]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+fn invoke_signed_raw_code(
+    instruction_addr: *const u8,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> (u64, ProgramResult) {
+    #[cfg(all(feature = "depth-guard", not(target_os = "solana")))]
+    let _depth = match depth_guard::enter() {
+        Ok(depth) => depth,
+        Err(error) => return (u64::from(error.clone()), Err(error)),
+    };
+
+    #[cfg(feature = "structured-logs")]
+    {
+        // SAFETY: callers pass the address of a live `StableInstruction`
+        let instruction = unsafe {
+            &*(instruction_addr
+                as *const solana_program::stable_layout::stable_instruction::StableInstruction)
+        };
+        log::log_cpi(
+            &instruction.program_id,
+            instruction.accounts.len() as usize,
+            instruction.data.len() as usize,
+        );
+    }
+
+    #[cfg(all(feature = "tracing", not(target_os = "solana")))]
+    let span = {
+        // SAFETY: callers pass the address of a live `StableInstruction`
+        let instruction = unsafe {
+            &*(instruction_addr
+                as *const solana_program::stable_layout::stable_instruction::StableInstruction)
+        };
+        trace::enter(&instruction.program_id)
+    };
+
+    let code = fire_raw(instruction_addr, account_infos, signers_seeds);
+    let result = program_result(code);
+
+    #[cfg(all(feature = "tracing", not(target_os = "solana")))]
+    span.record(&result);
+
+    #[cfg(feature = "log-cpi-errors")]
+    if let Err(error) = &result {
+        // SAFETY: callers pass the address of a live `StableInstruction`
+        let instruction = unsafe {
+            &*(instruction_addr
+                as *const solana_program::stable_layout::stable_instruction::StableInstruction)
+        };
+        log::log_cpi_error(&instruction.program_id, &instruction.accounts, error);
+    }
+
+    (code, result)
+}
+
+/// The syscall (or its off-chain stand-in) of [`invoke_signed_raw`],
+/// returning its raw code.
+#[inline(always)]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+fn fire_raw(
+    instruction_addr: *const u8,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> u64 {
+    #[cfg(target_os = "solana")]
+    {
+        let (account_infos_addr, account_infos_len) = account_infos_ptr(account_infos);
+        let (seeds_addr, seeds_len) = seeds_ptr(signers_seeds);
+        unsafe {
+            solana_program::syscalls::sol_invoke_signed_rust(
+                instruction_addr,
+                account_infos_addr,
+                account_infos_len,
+                seeds_addr,
+                seeds_len,
+            )
+        }
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    {
+        // SAFETY: callers pass the address of a live `StableInstruction`
+        let _instruction = unsafe {
+            &*(instruction_addr
+                as *const solana_program::stable_layout::stable_instruction::StableInstruction)
+        };
+
+        #[cfg(feature = "hooks")]
+        hooks::call(_instruction);
+
+        #[cfg(feature = "mock")]
+        return mock::invoke(_instruction, account_infos, signers_seeds);
+
+        #[cfg(not(feature = "mock"))]
+        {
+            core::hint::black_box((account_infos, signers_seeds));
+            panic!("not supported when target_os != solana");
+        }
+    }
+}
+
+/// Converts the return code of an invoke syscall.
+#[inline(always)]
+pub(crate) fn program_result(result: u64) -> ProgramResult {
+    #[cfg(feature = "log-errors")]
+    if result != solana_program::entrypoint::SUCCESS {
+        log::log_error_code(result);
+    }
+
+    match result {
+        solana_program::entrypoint::SUCCESS => Ok(()),
+        _ => Err(result.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::{
+        account_info::AccountInfo,
+        instruction::{AccountMeta, Instruction},
+        program_error::ProgramError,
+        pubkey::Pubkey,
+    };
+
+    use super::*;
+
+    #[test]
+    fn test_dry_run() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (from_key, to_key) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let (mut from_lamports, mut to_lamports) = (10, 10);
+        let (mut from_data, mut to_data) = ([0u8; 8], [0u8; 8]);
+        let from = AccountInfo::new(
+            &from_key,
+            true,
+            true,
+            &mut from_lamports,
+            &mut from_data,
+            &owner,
+            false,
+            0,
+        );
+        let to = AccountInfo::new(
+            &to_key,
+            false,
+            false,
+            &mut to_lamports,
+            &mut to_data,
+            &owner,
+            false,
+            0,
+        );
+
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new(from_key, true),
+                AccountMeta::new_readonly(to_key, false),
+            ],
+            data: vec![1, 2, 3],
+        };
+        let account_infos = [from.clone(), to.clone()];
+
+        // Well-formed
+        assert_eq!(dry_run(&instruction, &account_infos, &[]), Ok(()));
+
+        // Missing account
+        assert_eq!(
+            dry_run(&instruction, &account_infos[..1], &[]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        // Writability disagreement
+        let mut escalating = instruction.clone();
+        escalating.accounts[1].is_writable = true;
+        assert_eq!(
+            dry_run(&escalating, &account_infos, &[]),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // Seed limits
+        let seed = [0u8; 33];
+        assert_eq!(
+            dry_run(&instruction, &account_infos, &[&[&seed]]),
+            Err(ProgramError::MaxSeedLengthExceeded)
+        );
+        let signer: &[&[u8]] = &[b"vault"];
+        assert_eq!(
+            dry_run(&instruction, &account_infos, &[signer; MAX_SIGNERS + 1]),
+            Err(ProgramError::InvalidArgument)
+        );
+
+        // Borrow conflict
+        let guard = from.try_borrow_data().unwrap();
+        assert_eq!(
+            dry_run(&instruction, &account_infos, &[]),
+            Err(ProgramError::AccountBorrowFailed)
+        );
+        drop(guard);
+        assert_eq!(dry_run(&instruction, &account_infos, &[]), Ok(()));
+    }
+
+    #[cfg(not(feature = "mock"))]
+    #[test]
+    fn test_host_panic_points_to_caller() {
+        use std::sync::{Arc, Mutex};
+
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+
+        let location = Arc::new(Mutex::new(None));
+        let hook_location = location.clone();
+        let test_thread = std::thread::current().id();
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            // Other tests may panic concurrently
+            if std::thread::current().id() == test_thread {
+                *hook_location.lock().unwrap() = info
+                    .location()
+                    .map(|location| (location.file().to_string(), location.line()));
+            }
+        }));
+        let line = line!() + 1;
+        let result = std::panic::catch_unwind(|| invoke(&instruction, &[]));
+        std::panic::set_hook(previous_hook);
+
+        assert!(result.is_err());
+        assert_eq!(
+            location.lock().unwrap().take(),
+            Some((file!().to_string(), line))
+        );
+    }
+
+    #[test]
+    fn test_no_borrow_check() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 8]);
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new(key, false)],
+            data: vec![],
+        };
+        let account_infos = [info];
+
+        let guard = account_infos[0].try_borrow_data().unwrap();
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            invoke(&instruction, &account_infos)
+        }));
+        if checks::NO_BORROW_CHECK {
+            // Straight to the syscall, exactly like `invoke_unchecked`. It
+            // panics off-chain unless mocked
+            assert!(!matches!(result, Ok(Err(_))));
+        } else {
+            assert_eq!(
+                result.unwrap(),
+                Err(solana_program::program_error::ProgramError::AccountBorrowFailed)
+            );
+        }
+        // `dry_run` still reports the conflict
+        assert_eq!(
+            dry_run(&instruction, &account_infos, &[]),
+            Err(solana_program::program_error::ProgramError::AccountBorrowFailed)
+        );
+        drop(guard);
+    }
+
+    #[test]
+    fn test_solana_cpi_signatures() {
+        type Invoke =
+            for<'a, 'b, 'info> fn(&'a Instruction, &'b [AccountInfo<'info>]) -> ProgramResult;
+        type InvokeSigned = for<'a, 'b, 'c, 'd, 'e, 'info> fn(
+            &'a Instruction,
+            &'b [AccountInfo<'info>],
+            &'c [&'d [&'e [u8]]],
+        ) -> ProgramResult;
+
+        let _: [Invoke; 2] = [invoke, solana_program::program::invoke];
+        let _: [Invoke; 2] = [invoke_unchecked, solana_program::program::invoke_unchecked];
+        let _: [InvokeSigned; 2] = [invoke_signed, solana_program::program::invoke_signed];
+        let _: [InvokeSigned; 2] = [
+            invoke_signed_unchecked,
+            solana_program::program::invoke_signed_unchecked,
+        ];
+    }
+
+    #[test]
+    fn test_program_result() {
+        use solana_program::program_error::{ProgramError, MISSING_REQUIRED_SIGNATURES};
+
+        assert_eq!(program_result(solana_program::entrypoint::SUCCESS), Ok(()));
+        assert_eq!(
+            program_result(MISSING_REQUIRED_SIGNATURES),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+        assert_eq!(program_result(42), Err(ProgramError::Custom(42)));
+    }
+
+    /// `solana_cpi` maps every non-zero code with `ProgramError::from`
+    #[test]
+    fn test_program_result_matches_solana_cpi() {
+        use solana_program::program_error::ProgramError;
+
+        let builtin = (1..=64).map(|code: u64| code << 32);
+        let custom = [1, 42, 0x1770, u32::MAX as u64, (1 << 32) | 7, u64::MAX];
+        for code in builtin.chain(custom) {
+            assert_eq!(
+                program_result(code),
+                Err(ProgramError::from(code)),
+                "{code:#x}"
+            );
+        }
+    }
+
+    #[cfg(all(feature = "mock", not(feature = "panic-on-cpi-error")))]
+    #[test]
+    fn test_invoke_errors_match_solana_cpi() {
+        use solana_program::program_error::ProgramError;
+
+        thread_local! {
+            static ERROR: core::cell::RefCell<ProgramError> =
+                const { core::cell::RefCell::new(ProgramError::Custom(0)) };
+        }
+
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 0]);
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(key, false)],
+            data: vec![],
+        };
+
+        // Errors returned by the callee go through the syscall's return code
+        mock::set_mock_handler(|_, _, _| Err(ERROR.with_borrow(Clone::clone)));
+        let errors = [
+            ProgramError::Custom(0),
+            ProgramError::Custom(0x1770),
+            ProgramError::InvalidArgument,
+            ProgramError::MissingRequiredSignature,
+            ProgramError::AccountBorrowFailed,
+            ProgramError::IncorrectAuthority,
+        ];
+        for error in errors {
+            ERROR.set(error.clone());
+            let expected = ProgramError::from(u64::from(error));
+            assert_eq!(
+                invoke(&instruction, core::slice::from_ref(&info)),
+                Err(expected)
+            );
+        }
+        mock::clear_mock_handler();
+
+        // Borrow failures are caught before the syscall, as in `solana_cpi`
+        let _guard = info.try_borrow_mut_data().unwrap();
+        let expected = solana_program::program::invoke(&instruction, core::slice::from_ref(&info));
+        assert_eq!(expected, Err(ProgramError::AccountBorrowFailed));
+        if !checks::NO_BORROW_CHECK {
+            assert_eq!(invoke(&instruction, core::slice::from_ref(&info)), expected);
+        }
+    }
+
+    #[cfg(feature = "check-signers")]
+    #[test]
+    fn test_invoke_signed_checked_signers() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 0]);
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(key, true)],
+            data: vec![],
+        };
+
+        // Fails before reaching the syscall, which would panic off-chain
+        assert_eq!(
+            invoke_signed_checked_signers(&instruction, &[info], &[], &program_id),
+            Err(solana_program::program_error::ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[test]
+    fn test_seeds_with_bump() {
+        use solana_program::{program_error::ProgramError, pubkey::MAX_SEEDS};
+
+        let seed = [7u8; 4];
+        let bump = [255];
+        let mut buffer: [&[u8]; MAX_SEEDS] = [&[]; MAX_SEEDS];
+
+        // Exactly `MAX_SEEDS` seeds, bump included
+        let seeds = [&seed[..]; MAX_SEEDS - 1];
+        let with_bump = seeds_with_bump(&seeds, &bump, &mut buffer).unwrap();
+        assert_eq!(with_bump.len(), MAX_SEEDS);
+        assert_eq!(with_bump[..MAX_SEEDS - 1], seeds);
+        assert_eq!(with_bump[MAX_SEEDS - 1], &[255]);
+
+        // One too many
+        let seeds = [&seed[..]; MAX_SEEDS];
+        assert_eq!(
+            seeds_with_bump(&seeds, &bump, &mut buffer),
+            Err(ProgramError::MaxSeedLengthExceeded)
+        );
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+        assert_eq!(
+            invoke_signed_with_bump(&instruction, &[], &seeds, 255),
+            Err(ProgramError::MaxSeedLengthExceeded)
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_signed_as() {
+        use solana_program::program_error::ProgramError;
+
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (vault, bump) = Pubkey::find_program_address(&[b"vault", owner.as_ref()], &program_id);
+        let other = Pubkey::new_unique();
+        let mut lamports = [0u64; 2];
+        let mut data = [[0u8; 0]; 2];
+        let [vault_info, other_info]: [AccountInfo; 2] = {
+            let [vault_lamports, other_lamports] = &mut lamports;
+            let [vault_data, other_data] = &mut data;
+            [
+                AccountInfo::new(
+                    &vault,
+                    false,
+                    true,
+                    vault_lamports,
+                    vault_data,
+                    &owner,
+                    false,
+                    0,
+                ),
+                AccountInfo::new(
+                    &other,
+                    false,
+                    true,
+                    other_lamports,
+                    other_data,
+                    &owner,
+                    false,
+                    0,
+                ),
+            ]
+        };
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new_readonly(vault, true)],
+            data: vec![],
+        };
+        let seeds: &[&[u8]] = &[b"vault", owner.as_ref()];
+
+        // The callee only sees the vault's seeds
+        mock::set_mock_handler(|_, _, signers_seeds| {
+            assert_eq!(signers_seeds.len(), 1);
+            assert_eq!(signers_seeds[0].len(), 3);
+            Ok(())
+        });
+        let matching = invoke_signed_as(&instruction, &[], &vault_info, seeds, bump, &program_id);
+        mock::clear_mock_handler();
+        assert_eq!(matching, Ok(()));
+
+        // Another account, another bump, other seeds or another program id
+        let wrong_bump = if bump == 255 { 254 } else { bump + 1 };
+        for result in [
+            invoke_signed_as(&instruction, &[], &other_info, seeds, bump, &program_id),
+            invoke_signed_as(
+                &instruction,
+                &[],
+                &vault_info,
+                seeds,
+                wrong_bump,
+                &program_id,
+            ),
+            invoke_signed_as(
+                &instruction,
+                &[],
+                &vault_info,
+                &[b"vault"],
+                bump,
+                &program_id,
+            ),
+            invoke_signed_as(&instruction, &[], &vault_info, seeds, bump, &owner),
+        ] {
+            assert_eq!(result, Err(ProgramError::InvalidSeeds));
+        }
+    }
+
+    #[test]
+    fn test_invoke_expecting_discriminator() {
+        use solana_program::program_error::ProgramError;
+
+        let discriminator = [1, 2, 3, 4, 5, 6, 7, 8];
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: [discriminator, [9; 8]].concat(),
+        };
+
+        // Matching: reaches the syscall, which panics off-chain unless mocked
+        let result = std::panic::catch_unwind(|| {
+            invoke_expecting_discriminator(&instruction, &[], discriminator)
+        });
+        assert!(!matches!(result, Ok(Err(_))));
+
+        // Mismatching
+        assert_eq!(
+            invoke_expecting_discriminator(&instruction, &[], [8, 7, 6, 5, 4, 3, 2, 1]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+
+        // Too short
+        instruction.data.truncate(7);
+        assert_eq!(
+            invoke_expecting_discriminator(&instruction, &[], discriminator),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+
+    #[cfg(all(feature = "mock", debug_assertions))]
+    #[test]
+    fn test_invoke_exact() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (mut lamports_0, mut lamports_1) = (0, 0);
+        let (mut data_0, mut data_1) = ([0u8; 0], [0u8; 0]);
+        let account_infos = [
+            AccountInfo::new(
+                &keys[0],
+                false,
+                true,
+                &mut lamports_0,
+                &mut data_0,
+                &owner,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &keys[1],
+                false,
+                true,
+                &mut lamports_1,
+                &mut data_1,
+                &owner,
+                false,
+                0,
+            ),
+        ];
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new_readonly(keys[1], false),
+            ],
+            data: vec![],
+        };
+
+        // Matching
+        assert_eq!(invoke_exact(&instruction, &account_infos, &[]), Ok(()));
+
+        // Mismatching, in either direction
+        let fewer = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            invoke_exact(&instruction, core::array::from_ref(&account_infos[0]), &[])
+        }));
+        assert!(fewer.is_err());
+        let more = Instruction {
+            accounts: instruction.accounts[..1].to_vec(),
+            ..instruction.clone()
+        };
+        let more = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            invoke_exact(&more, &account_infos, &[])
+        }));
+        assert!(more.is_err());
+    }
+
+    #[cfg(all(feature = "mock", debug_assertions))]
+    #[test]
+    fn test_invoke_expecting_lamport_delta() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (mut from_lamports, mut to_lamports) = (100, 0);
+        let (mut from_data, mut to_data) = ([0u8; 0], [0u8; 0]);
+        let account_infos = [
+            AccountInfo::new(
+                &keys[0],
+                true,
+                true,
+                &mut from_lamports,
+                &mut from_data,
+                &owner,
+                false,
+                0,
+            ),
+            AccountInfo::new(
+                &keys[1],
+                false,
+                true,
+                &mut to_lamports,
+                &mut to_data,
+                &owner,
+                false,
+                0,
+            ),
+        ];
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                solana_program::instruction::AccountMeta::new(keys[0], true),
+                solana_program::instruction::AccountMeta::new(keys[1], false),
+            ],
+            data: vec![],
+        };
+
+        // A transfer of 10 lamports
+        mock::set_mock_handler(|_, account_infos, _| {
+            **account_infos[0].try_borrow_mut_lamports()? -= 10;
+            **account_infos[1].try_borrow_mut_lamports()? += 10;
+            Ok(())
+        });
+
+        // Matching
+        let matching = [
+            invoke_expecting_lamport_delta(&instruction, &account_infos, 0, -10, &[]),
+            invoke_expecting_lamport_delta(&instruction, &account_infos, 1, 10, &[]),
+        ];
+
+        // Mismatching
+        let mismatching = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            invoke_expecting_lamport_delta(&instruction, &account_infos, 1, 9, &[])
+        }));
+        mock::clear_mock_handler();
+
+        assert_eq!(matching, [Ok(()), Ok(())]);
+        assert!(mismatching.is_err());
+        assert_eq!(account_infos[0].lamports(), 70);
+        assert_eq!(account_infos[1].lamports(), 30);
+
+        // Errors, not panics, and the CPI isn't fired
+        assert_eq!(
+            invoke_expecting_lamport_delta(&instruction, &account_infos, 2, 0, &[]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        if cfg!(debug_assertions) || !checks::NO_BORROW_CHECK {
+            let _guard = account_infos[1].try_borrow_mut_lamports().unwrap();
+            assert_eq!(
+                invoke_expecting_lamport_delta(&instruction, &account_infos, 1, 0, &[]),
+                Err(ProgramError::AccountBorrowFailed)
+            );
+        }
+        assert_eq!(account_infos[0].lamports(), 70);
+    }
+
+    #[cfg(all(
+        feature = "log-errors",
+        feature = "mock",
+        not(feature = "panic-on-cpi-error")
+    ))]
+    #[test]
+    fn test_log_errors() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+
+        mock::set_mock_handler(|_, _, _| {
+            Err(solana_program::program_error::ProgramError::Custom(0xbeef))
+        });
+        let _ = mock::take_logs();
+        let result = invoke(&instruction, &[]);
+        mock::clear_mock_handler();
+
+        assert!(result.is_err());
+        let mut expected = vec![];
+        if cfg!(feature = "structured-logs") {
+            expected.push(format!("CPI|{}|0|0", instruction.program_id));
+        }
+        expected.push("invoke failed with code 0xbeef".to_string());
+        if cfg!(feature = "log-cpi-errors") {
+            expected.push(format!(
+                "CPI to {} failed: Custom program error: 0xbeef",
+                instruction.program_id
+            ));
+        }
+        assert_eq!(mock::take_logs(), expected);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_and_load() {
+        use solana_program::{
+            program_error::ProgramError,
+            program_pack::{IsInitialized, Pack, Sealed},
+        };
+
+        /// The fields of a token account that a transfer touches
+        #[derive(Debug, PartialEq)]
+        struct Balance {
+            amount: u64,
+            is_initialized: bool,
+        }
+
+        impl Sealed for Balance {}
+
+        impl IsInitialized for Balance {
+            fn is_initialized(&self) -> bool {
+                self.is_initialized
+            }
+        }
+
+        impl Pack for Balance {
+            const LEN: usize = 9;
+
+            fn pack_into_slice(&self, dst: &mut [u8]) {
+                dst[..8].copy_from_slice(&self.amount.to_le_bytes());
+                dst[8] = self.is_initialized as u8;
+            }
+
+            fn unpack_from_slice(src: &[u8]) -> Result<Balance, ProgramError> {
+                Ok(Balance {
+                    amount: u64::from_le_bytes(src[..8].try_into().unwrap()),
+                    is_initialized: src[8] == 1,
+                })
+            }
+        }
+
+        let token_program = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = [0u8; Balance::LEN];
+        Balance {
+            amount: 100,
+            is_initialized: true,
+        }
+        .pack_into_slice(&mut data);
+        let destination = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &token_program,
+            false,
+            0,
+        );
+        let transfer = Instruction {
+            program_id: token_program,
+            accounts: vec![AccountMeta::new(key, false)],
+            data: 25u64.to_le_bytes().to_vec(),
+        };
+
+        // A transfer of `data` tokens into the account
+        mock::set_mock_handler(|instruction, account_infos, _| {
+            let amount = u64::from_le_bytes(instruction.data[..8].try_into().unwrap());
+            let mut data = account_infos[0].try_borrow_mut_data()?;
+            let mut balance = Balance::unpack(&data)?;
+            balance.amount += amount;
+            balance.pack_into_slice(&mut data);
+            Ok(())
+        });
+        let loaded = invoke_and_load::<Balance>(
+            &transfer,
+            core::slice::from_ref(&destination),
+            &destination,
+            &[],
+        );
+        mock::clear_mock_handler();
+
+        assert_eq!(
+            loaded,
+            Ok(Balance {
+                amount: 125,
+                is_initialized: true,
+            })
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_if_enough_compute() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![0; 500],
+        };
+        mock::set_mock_handler(|_, _, _| {
+            mock::consume_simulated_compute(100);
+            Ok(())
+        });
+        mock::record_invocations();
+        let min_cu = mock::INVOKE_UNITS + 500 / mock::CPI_BYTES_PER_UNIT + 100;
+
+        // Fires while enough units remain, then skips
+        mock::set_simulated_compute(2 * min_cu + 1);
+        assert_eq!(
+            invoke_if_enough_compute(&instruction, &[], min_cu, &[]),
+            Ok(true)
+        );
+        assert_eq!(
+            invoke_if_enough_compute(&instruction, &[], min_cu, &[]),
+            Ok(true)
+        );
+        assert_eq!(mock::remaining_simulated_compute(), Some(1));
+        assert_eq!(
+            invoke_if_enough_compute(&instruction, &[], min_cu, &[]),
+            Ok(false)
+        );
+        assert_eq!(mock::invocation_count(), 2);
+        assert_eq!(mock::remaining_simulated_compute(), Some(1));
+
+        // Exactly enough units
+        mock::set_simulated_compute(min_cu);
+        assert_eq!(
+            invoke_if_enough_compute(&instruction, &[], min_cu, &[]),
+            Ok(true)
+        );
+        assert_eq!(mock::take_invocations().len(), 3);
+
+        mock::clear_simulated_compute();
+        mock::clear_mock_handler();
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_data_window() {
+        let program_id = Pubkey::new_unique();
+        let buffer: Vec<u8> = (0..30).collect();
+
+        thread_local! {
+            static WINDOWS: core::cell::RefCell<Vec<Vec<u8>>> = const { core::cell::RefCell::new(Vec::new()) };
+        }
+        mock::set_mock_handler(|instruction, _, _| {
+            WINDOWS.with_borrow_mut(|windows| windows.push(instruction.data.clone()));
+            Ok(())
+        });
+        for window in buffer.chunks(10) {
+            assert_eq!(
+                invoke_data_window(&program_id, &[], window, &[], &[]),
+                Ok(())
+            );
+        }
+        mock::clear_mock_handler();
+
+        let windows = WINDOWS.take();
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows.concat(), buffer);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_from_infos() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let (payer_key, pda_key, readonly_key) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let (mut payer_lamports, mut pda_lamports, mut readonly_lamports) = (0, 0, 0);
+        let (mut payer_data, mut pda_data, mut readonly_data) = ([0u8; 0], [0u8; 0], [0u8; 0]);
+        let payer = AccountInfo::new(
+            &payer_key,
+            true,
+            true,
+            &mut payer_lamports,
+            &mut payer_data,
+            &owner,
+            false,
+            0,
+        );
+        let pda = AccountInfo::new(
+            &pda_key,
+            false,
+            true,
+            &mut pda_lamports,
+            &mut pda_data,
+            &owner,
+            false,
+            0,
+        );
+        let readonly = AccountInfo::new(
+            &readonly_key,
+            false,
+            false,
+            &mut readonly_lamports,
+            &mut readonly_data,
+            &owner,
+            false,
+            0,
+        );
+        let expected = Instruction::new_with_bytes(
+            program_id,
+            &[1, 2, 3],
+            vec![
+                AccountMeta::new(payer_key, true),
+                AccountMeta::new(pda_key, false),
+                AccountMeta::new_readonly(readonly_key, false),
+            ],
+        );
+
+        mock::record_invocations();
+        let infos = [payer.clone(), pda.clone(), readonly.clone()];
+        assert_eq!(
+            invoke_from_infos(&program_id, &infos, &[1, 2, 3], &[]),
+            Ok(())
+        );
+
+        // A PDA signs through a clone of its account info marked as signer
+        let mut signing_pda = pda.clone();
+        signing_pda.is_signer = true;
+        let signing_infos = [payer.clone(), signing_pda, readonly.clone()];
+        assert_eq!(
+            invoke_from_infos(&program_id, &signing_infos, &[1, 2, 3], &[&[b"pda"]]),
+            Ok(())
+        );
+        let fired = mock::take_invocations();
+        assert_eq!(fired[0], expected);
+        assert_eq!(fired[1].accounts[0], expected.accounts[0]);
+        assert_eq!(fired[1].accounts[1], AccountMeta::new(pda_key, true));
+        assert_eq!(fired[1].accounts[2], expected.accounts[2]);
+
+        // Seeds with no signer to sign for are rejected before firing
+        assert_eq!(
+            invoke_from_infos(&program_id, &[pda, readonly], &[], &[&[b"pda"]]),
+            Err(ProgramError::MissingRequiredSignature)
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_reusing() {
+        const ACCOUNT_METAS: &[AccountMeta] = &[AccountMeta {
+            pubkey: Pubkey::new_from_array([1; 32]),
+            is_signer: false,
+            is_writable: true,
+        }];
+        const DATA: &[u8] = &[7, 8, 9];
+        let owner = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mut data = [0u8; 0];
+        let account_info = AccountInfo::new(
+            &ACCOUNT_METAS[0].pubkey,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let account_infos = [account_info];
+
+        thread_local! {
+            static CALLEES: core::cell::RefCell<Vec<Pubkey>> = const { core::cell::RefCell::new(Vec::new()) };
+        }
+        mock::set_mock_handler(|instruction, _, _| {
+            assert_eq!(instruction.accounts, ACCOUNT_METAS);
+            assert_eq!(instruction.data, DATA);
+            CALLEES.with_borrow_mut(|callees| callees.push(instruction.program_id));
+            Ok(())
+        });
+        let program_ids = [Pubkey::new_unique(), Pubkey::new_unique()];
+        for program_id in &program_ids {
+            assert_eq!(
+                invoke_reusing(program_id, ACCOUNT_METAS, DATA, &account_infos, &[]),
+                Ok(())
+            );
+        }
+        mock::clear_mock_handler();
+        assert_eq!(CALLEES.take(), program_ids);
+
+        // Checked like `invoke_signed`
+        if !checks::NO_BORROW_CHECK {
+            let _guard = account_infos[0].try_borrow_data().unwrap();
+            assert_eq!(
+                invoke_reusing(&program_ids[0], ACCOUNT_METAS, DATA, &account_infos, &[]),
+                Err(ProgramError::AccountBorrowFailed)
+            );
+        }
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_signed_maybe_uninit() {
+        use core::mem::MaybeUninit;
+
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (mut lamports, mut data) = ([0u64; 2], [[0u8; 0]; 2]);
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new_readonly(keys[1], false),
+            ],
+        );
+
+        // Only the first two of four slots are initialized
+        let mut infos = [const { MaybeUninit::<AccountInfo>::uninit() }; 4];
+        for ((info, key), (lamports, data)) in infos
+            .iter_mut()
+            .zip(keys.iter())
+            .zip(lamports.iter_mut().zip(data.iter_mut()))
+        {
+            info.write(AccountInfo::new(
+                key, false, true, lamports, data, &owner, false, 0,
+            ));
+        }
+
+        thread_local! {
+            static SEEN: core::cell::RefCell<Vec<Pubkey>> = const { core::cell::RefCell::new(Vec::new()) };
+        }
+        mock::set_mock_handler(|_, account_infos, _| {
+            SEEN.set(account_infos.iter().map(|info| *info.key).collect());
+            Ok(())
+        });
+        // SAFETY: `infos[..2]` is initialized and not touched during the call
+        let result = unsafe { invoke_signed_maybe_uninit(&instruction, &infos, 2, &[]) };
+        mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(SEEN.take(), keys);
+
+        for info in &mut infos[..2] {
+            // SAFETY: initialized above, and dropped once
+            unsafe { info.assume_init_drop() };
+        }
+    }
+
+    #[cfg(all(feature = "mock", not(feature = "panic-on-cpi-error")))]
+    #[test]
+    fn test_invoke_custom_code() {
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+        let failing = Instruction::new_with_bytes(instruction.program_id, &[1], vec![]);
+
+        // The callee fails with a custom code when given data
+        mock::set_mock_handler(|instruction, _, _| match instruction.data.first() {
+            Some(_) => Err(ProgramError::Custom(6001)),
+            None => Ok(()),
+        });
+        let succeeded = invoke_custom_code(&instruction, &[], &[]);
+        let failed = invoke_custom_code(&failing, &[], &[]);
+        mock::set_mock_handler(|_, _, _| Err(ProgramError::InvalidAccountData));
+        let builtin = invoke_custom_code(&instruction, &[], &[]);
+        mock::set_mock_handler(|_, _, _| Err(ProgramError::Custom(0)));
+        let custom_zero = invoke_custom_code(&instruction, &[], &[]);
+        // Codes `ProgramError` doesn't know come back as they are, rather
+        // than truncated to a `Custom` code
+        let unknown_codes = [(1 << 32) | 7, 100 << 32].map(|code| {
+            mock::set_mock_error_code(code);
+            let result = invoke_custom_code(&instruction, &[], &[]);
+            mock::clear_mock_error_code();
+            result
+        });
+        mock::clear_mock_handler();
+
+        assert_eq!(succeeded, Ok(()));
+        assert_eq!(failed, Err(6001));
+        assert_eq!(builtin, Err(u64::from(ProgramError::InvalidAccountData)));
+        assert_eq!(custom_zero, Err(solana_program::program_error::CUSTOM_ZERO));
+        assert_eq!(unknown_codes, [Err((1 << 32) | 7), Err(100 << 32)]);
+
+        // Errors of the checks are converted
+        let too_many = Instruction::new_with_bytes(
+            instruction.program_id,
+            &[],
+            vec![
+                AccountMeta::new_readonly(Pubkey::new_unique(), false);
+                MAX_CPI_INSTRUCTION_ACCOUNTS + 1
+            ],
+        );
+        assert_eq!(
+            invoke_custom_code(&too_many, &[], &[]),
+            Err(u64::from(ProgramError::InvalidArgument))
+        );
+    }
+
+    #[cfg(all(feature = "mock", not(feature = "panic-on-cpi-error")))]
+    #[test]
+    fn test_try_invoke_signed() {
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [0u64; 3];
+        let mut data = [[0u8; 0]; 3];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new_readonly(keys[1], false),
+                AccountMeta::new(keys[2], false),
+                AccountMeta::new(keys[0], false),
+            ],
+            data: vec![],
+        };
+
+        assert_eq!(try_invoke_signed(&instruction, &account_infos, &[]), Ok(()));
+
+        // A borrow of `keys[0]` conflicts with the account meta at index 2
+        if !checks::NO_BORROW_CHECK {
+            let _guard = account_infos[0].try_borrow_data().unwrap();
+            assert_eq!(
+                try_invoke_signed(&instruction, &account_infos, &[]),
+                Err((ProgramError::AccountBorrowFailed, 2))
+            );
+        }
+
+        // A failed CPI has no offending account
+        mock::set_mock_handler(|_, _, _| Err(ProgramError::Custom(3)));
+        let result = try_invoke_signed(&instruction, &account_infos, &[]);
+        mock::clear_mock_handler();
+        assert_eq!(result, Err((ProgramError::Custom(3), 3)));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_accumulating() {
+        let instruction = Instruction::new_with_bytes(Pubkey::new_unique(), &[0; 500], vec![]);
+        mock::set_mock_handler(|_, _, _| {
+            mock::consume_simulated_compute(100);
+            Ok(())
+        });
+        mock::set_simulated_compute(10_000);
+
+        let mut cu_used = 0;
+        assert_eq!(
+            invoke_accumulating(&instruction, &[], &mut cu_used, &[]),
+            Ok(())
+        );
+        let per_cpi = mock::INVOKE_UNITS + 500 / mock::CPI_BYTES_PER_UNIT + 100;
+        assert_eq!(cu_used, per_cpi);
+        assert_eq!(
+            invoke_accumulating(&instruction, &[], &mut cu_used, &[]),
+            Ok(())
+        );
+        mock::clear_simulated_compute();
+        mock::clear_mock_handler();
+
+        assert_eq!(cu_used, 2 * per_cpi);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_checked_sizes() {
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 165]);
+        let account_info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let account_infos = [account_info];
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![AccountMeta::new(key, false)],
+        );
+        mock::record_invocations();
+
+        let sized = invoke_checked_sizes(&instruction, &account_infos, &[(0, 165)], &[]);
+        let too_small = invoke_checked_sizes(&instruction, &account_infos, &[(0, 166)], &[]);
+
+        assert_eq!(sized, Ok(()));
+        assert_eq!(too_small, Err(ProgramError::AccountDataTooSmall));
+        assert_eq!(mock::take_invocations().len(), 1);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_signed_sorted_seeds() {
+        const PROGRAM_ID: Pubkey = Pubkey::new_from_array([8; 32]);
+        let (vault_a, bump_a) = Pubkey::find_program_address(&[b"a"], &PROGRAM_ID);
+        let (vault_b, bump_b) = Pubkey::find_program_address(&[b"b"], &PROGRAM_ID);
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new_readonly(vault_a, true),
+                AccountMeta::new_readonly(vault_b, true),
+            ],
+        );
+        let (seeds_a, seeds_b): (&[&[u8]], &[&[u8]]) = (&[b"a", &[bump_a]], &[b"b", &[bump_b]]);
+
+        // The callee checks that the signers come sorted and cover the vaults
+        mock::set_mock_handler(|instruction, _, signers_seeds| {
+            let signers: Vec<Pubkey> = signers_seeds
+                .iter()
+                .map(|seeds| Pubkey::create_program_address(seeds, &PROGRAM_ID).unwrap())
+                .collect();
+            assert!(signers.is_sorted());
+            for account_meta in instruction.accounts.iter() {
+                if !signers.contains(&account_meta.pubkey) {
+                    return Err(ProgramError::MissingRequiredSignature);
+                }
+            }
+            Ok(())
+        });
+        for signers_seeds in [[seeds_a, seeds_b], [seeds_b, seeds_a]] {
+            assert_eq!(
+                invoke_signed_sorted_seeds(&instruction, &[], &signers_seeds, &PROGRAM_ID),
+                Ok(())
+            );
+        }
+        mock::clear_mock_handler();
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_whitelisted() {
+        let allowed = [Pubkey::new_unique(), Pubkey::new_unique()];
+        mock::record_invocations();
+
+        for program_id in allowed {
+            let instruction = Instruction::new_with_bytes(program_id, &[], vec![]);
+            assert_eq!(invoke_whitelisted(&instruction, &[], &allowed, &[]), Ok(()));
+        }
+        let spoofed = Instruction::new_with_bytes(Pubkey::new_unique(), &[], vec![]);
+        assert_eq!(
+            invoke_whitelisted(&spoofed, &[], &allowed, &[]),
+            Err(ProgramError::IncorrectProgramId)
+        );
+        assert_eq!(mock::take_invocations().len(), 2);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_or_skip() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let placeholder = Instruction::new_with_bytes(program_id, &[], vec![]);
+        let with_data = Instruction::new_with_bytes(program_id, &[1], vec![]);
+        let with_accounts = Instruction::new_with_bytes(
+            program_id,
+            &[],
+            vec![AccountMeta::new_readonly(key, false)],
+        );
+        mock::record_invocations();
+
+        assert_eq!(invoke_or_skip(&placeholder, &[], &[]), Ok(()));
+        assert_eq!(mock::invocation_count(), 0);
+        assert_eq!(invoke_or_skip(&with_data, &[], &[]), Ok(()));
+        assert_eq!(invoke_or_skip(&with_accounts, &[], &[]), Ok(()));
+        assert_eq!(mock::take_invocations().len(), 2);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_once() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new_readonly(key, false)],
+            data: vec![1, 2, 3],
+        };
+        mock::record_invocations();
+
+        let mut seen = std::collections::HashSet::new();
+        let first = invoke_once(&instruction, &[], &mut seen, &[]);
+        let repeated = invoke_once(&instruction.clone(), &[], &mut seen, &[]);
+        // Any difference in the flags, the data or the program id is another
+        // instruction
+        let others = [
+            Instruction {
+                accounts: vec![AccountMeta::new_readonly(key, true)],
+                ..instruction.clone()
+            },
+            Instruction {
+                data: vec![1, 2],
+                ..instruction.clone()
+            },
+            Instruction {
+                program_id: Pubkey::new_unique(),
+                ..instruction.clone()
+            },
+        ]
+        .map(|other| invoke_once(&other, &[], &mut seen, &[]));
+
+        assert_eq!(first, Ok(true));
+        assert_eq!(repeated, Ok(false));
+        assert_eq!(others, [Ok(true), Ok(true), Ok(true)]);
+        assert_eq!(mock::take_invocations().len(), 4);
+        assert_eq!(seen.len(), 4);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_sorted() {
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [0u64; 3];
+        let mut data = [[0u8; 0]; 3];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(keys[2], false),
+                AccountMeta::new_readonly(keys[0], false),
+                AccountMeta::new(keys[1], false),
+            ],
+            data: vec![1, 2, 3],
+        };
+
+        // The callee sees sorted metas, matching infos, and each writable
+        // account gets a lamport
+        mock::set_mock_handler(|instruction, account_infos, _| {
+            assert!(instruction
+                .accounts
+                .windows(2)
+                .all(|w| w[0].pubkey <= w[1].pubkey));
+            assert_eq!(instruction.data, [1, 2, 3]);
+            for (account_meta, account_info) in instruction.accounts.iter().zip(account_infos) {
+                assert_eq!(account_meta.pubkey, *account_info.key);
+                if account_meta.is_writable {
+                    **account_info.try_borrow_mut_lamports()? += 1;
+                }
+            }
+            Ok(())
+        });
+        let result = invoke_sorted(&instruction, &account_infos, &[]);
+        mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        let balances: Vec<u64> = account_infos.iter().map(|info| info.lamports()).collect();
+        assert_eq!(balances, [0, 1, 1]);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_auto_order() {
+        use solana_program::program_error::ProgramError;
+
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..6).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [0u64; 6];
+        let mut data = [[0u8; 0]; 6];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(keys[4], false),
+                AccountMeta::new_readonly(keys[1], false),
+                AccountMeta::new(keys[3], false),
+            ],
+            data: vec![],
+        };
+
+        // A larger, shuffled pool
+        let pool = [5, 3, 0, 1, 4, 2].map(|i| account_infos[i].clone());
+        mock::set_mock_handler(|instruction, account_infos, _| {
+            assert_eq!(account_infos.len(), instruction.accounts.len());
+            for (account_meta, account_info) in instruction.accounts.iter().zip(account_infos) {
+                assert_eq!(account_meta.pubkey, *account_info.key);
+            }
+            Ok(())
+        });
+        let result = invoke_auto_order(&instruction, &pool, &[]);
+
+        // Missing from the pool
+        instruction
+            .accounts
+            .push(AccountMeta::new(Pubkey::new_unique(), false));
+        let missing = invoke_auto_order(&instruction, &pool, &[]);
+        mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(missing, Err(ProgramError::NotEnoughAccountKeys));
+    }
+
+    #[cfg(all(feature = "mock", not(feature = "panic-on-cpi-error")))]
+    #[test]
+    fn test_account_infos_behind_refcell() {
+        use core::cell::RefCell;
+
+        use solana_program::program_error::ProgramError;
+
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 8]);
+        let accounts = RefCell::new(vec![AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        )]);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(key, false)],
+            data: vec![],
+        };
+
+        mock::set_mock_handler(|_, account_infos, _| {
+            account_infos[0].try_borrow_mut_data()?[0] += 1;
+            Ok(())
+        });
+
+        // Borrowing the wrapper, even mutably, doesn't borrow the accounts
+        let result = invoke(&instruction, &accounts.borrow_mut());
+        let borrowed_wrapper = invoke(&instruction, &accounts.borrow());
+
+        // A guard on the account data reached through the wrapper does
+        let held = {
+            let account_infos = accounts.borrow();
+            let _guard = account_infos[0].try_borrow_data().unwrap();
+            invoke(&instruction, &account_infos)
+        };
+        mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(borrowed_wrapper, Ok(()));
+        assert_eq!(accounts.borrow()[0].try_borrow_data().unwrap()[0], 2);
+        if !checks::NO_BORROW_CHECK {
+            assert_eq!(held, Err(ProgramError::AccountBorrowFailed));
+        }
+    }
+
+    #[cfg(all(feature = "mock", not(feature = "panic-on-cpi-error")))]
+    #[test]
+    fn test_invoke_many_best_effort() {
+        use solana_program::program_error::ProgramError;
+
+        let instructions = [0, 1, 2].map(|i| Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![i],
+        });
+
+        // The middle instruction fails
+        mock::set_mock_handler(|instruction, _, _| {
+            if instruction.data[0] == 1 {
+                return Err(ProgramError::Custom(1));
+            }
+            Ok(())
+        });
+        let _ = mock::take_logs();
+        mock::record_invocations();
+        let results = invoke_many_best_effort(&instructions, &[]);
+        mock::clear_mock_handler();
+
+        assert_eq!(results, [Ok(()), Err(ProgramError::Custom(1)), Ok(())]);
+        let fired: Vec<_> = mock::take_invocations()
+            .into_iter()
+            .map(|instruction| instruction.data[0])
+            .collect();
+        assert_eq!(fired, [0, 1, 2]);
+        let failures: Vec<String> = mock::take_logs()
+            .into_iter()
+            .filter(|log| log.starts_with("invoke 1"))
+            .collect();
+        assert_eq!(failures, ["invoke 1 failed: Custom program error: 0x1"]);
+    }
+
+    #[cfg(all(feature = "log-cpi-errors", feature = "mock"))]
+    #[test]
+    fn test_log_cpi_errors() {
+        let program_id = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new_readonly(key, true)],
+            data: vec![],
+        };
+
+        mock::set_mock_handler(|_, _, _| {
+            Err(solana_program::program_error::ProgramError::Custom(3))
+        });
+        let _ = mock::take_logs();
+        let result = std::panic::catch_unwind(|| invoke_unchecked(&instruction, &[]));
+        mock::clear_mock_handler();
+
+        if cfg!(feature = "panic-on-cpi-error") {
+            assert!(result.is_err());
+        } else {
+            assert_eq!(
+                result.unwrap(),
+                Err(solana_program::program_error::ProgramError::Custom(3))
+            );
+        }
+        let logs = mock::take_logs();
+        assert!(logs.contains(&format!(
+            "CPI to {program_id} failed: Custom program error: 0x3"
+        )));
+        assert!(logs.contains(&format!(
+            "  account 0: {key} (signer: true, writable: false)"
+        )));
+    }
+
+    #[cfg(all(feature = "structured-logs", feature = "mock"))]
+    #[test]
+    fn test_structured_logs() {
+        let program_id = Pubkey::new_unique();
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                solana_program::instruction::AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                solana_program::instruction::AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            ],
+            data: vec![0; 12],
+        };
+
+        let _ = mock::take_logs();
+        assert_eq!(invoke_unchecked(&instruction, &[]), Ok(()));
+        assert_eq!(mock::take_logs(), [format!("CPI|{program_id}|2|12")]);
+    }
+
+    #[test]
+    fn test_invoke_releasing() {
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 8]);
+        let info = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new(key, false)],
+            data: vec![],
+        };
+        let account_infos = [info];
+
+        // Forgetting to drop the guard fails the borrow check
+        let mut guard = account_infos[0].try_borrow_mut_data().unwrap();
+        guard[0] = 1;
+        if !checks::NO_BORROW_CHECK {
+            assert_eq!(
+                invoke(&instruction, &account_infos),
+                Err(solana_program::program_error::ProgramError::AccountBorrowFailed)
+            );
+        }
+
+        // Reaches the syscall, which panics off-chain unless mocked
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            invoke_releasing(&instruction, &account_infos, guard, &[])
+        }));
+        assert!(!matches!(result, Ok(Err(_))));
+        assert_eq!(account_infos[0].try_borrow_data().unwrap()[0], 1);
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_max_cpi_instruction_accounts() {
+        let key = Pubkey::new_unique();
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![AccountMeta::new_readonly(key, false); MAX_CPI_INSTRUCTION_ACCOUNTS],
+            data: vec![],
+        };
+        assert_eq!(invoke(&instruction, &[]), Ok(()));
+        assert_eq!(invoke_fast(&instruction, &[]), Ok(()));
+
+        instruction
+            .accounts
+            .push(AccountMeta::new_readonly(key, false));
+        assert_eq!(
+            invoke(&instruction, &[]),
+            Err(solana_program::program_error::ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            invoke_fast(&instruction, &[]),
+            Err(solana_program::program_error::ProgramError::InvalidArgument)
+        );
+    }
+
+    #[test]
+    fn test_account_infos_ptr() {
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (0, [0u8; 0]);
+        let info = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let account_infos = [info.clone(), info.clone(), info];
+
+        let (addr, len) = account_infos_ptr(&account_infos);
+        assert_eq!(len, 3);
+        assert_eq!(addr, &account_infos[0] as *const AccountInfo as *const u8);
+        assert_eq!(account_infos_ptr(&account_infos[..0]).1, 0);
+    }
+
+    #[test]
+    fn test_seeds_ptr() {
+        let vault: &[&[u8]] = &[b"vault", &[255]];
+        let escrow: &[&[u8]] = &[b"escrow"];
+        let signers_seeds = [vault, escrow];
+
+        let (addr, len) = seeds_ptr(&signers_seeds);
+        assert_eq!(len, signers_seeds.len() as u64);
+        assert_eq!(addr, signers_seeds.as_ptr() as *const u8);
+        assert_eq!(seeds_ptr(&[]).1, 0);
+    }
+
+    /// The runtime reads the account infos passed to the syscall at these
+    /// offsets (on the 64-bit SBF target). If this fails, upstream changed
+    /// the layout of `AccountInfo` and the syscall path must be revisited.
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn test_account_info_layout() {
+        use core::mem::{offset_of, size_of};
+
+        assert_eq!(size_of::<AccountInfo>(), 48);
+        assert_eq!(offset_of!(AccountInfo, key), 0);
+        assert_eq!(offset_of!(AccountInfo, lamports), 8);
+        assert_eq!(offset_of!(AccountInfo, data), 16);
+        assert_eq!(offset_of!(AccountInfo, owner), 24);
+        assert_eq!(offset_of!(AccountInfo, rent_epoch), 32);
+        assert_eq!(offset_of!(AccountInfo, is_signer), 40);
+        assert_eq!(offset_of!(AccountInfo, is_writable), 41);
+        assert_eq!(offset_of!(AccountInfo, executable), 42);
     }
 }
@@ -0,0 +1,47 @@
+#![allow(unused)] // only used by the logging features and the mock path
+
+use solana_program::{instruction::AccountMeta, program_error::ProgramError, pubkey::Pubkey};
+
+/// Logs a message from this crate. Off-chain with the `mock` feature, the
+/// message is also captured for [`take_logs`](crate::mock::take_logs).
+#[cold]
+pub(crate) fn log(message: &str) {
+    #[cfg(all(feature = "mock", not(target_os = "solana")))]
+    crate::mock::capture_log(message);
+
+    solana_program::log::sol_log(message);
+}
+
+/// Logs a CPI about to be fired as `CPI|<program>|<n_accounts>|<data_len>`,
+/// with the program id in base58 and the counts in decimal.
+pub(crate) fn log_cpi(program_id: &Pubkey, accounts_len: usize, data_len: usize) {
+    log(&format!("CPI|{program_id}|{accounts_len}|{data_len}"));
+}
+
+/// Logs the raw return code of a failed invoke syscall, which converting it
+/// to a `ProgramError` may obscure.
+#[cold]
+pub(crate) fn log_error_code(code: u64) {
+    log(&format!("invoke failed with code {code:#x}"));
+}
+
+/// Logs the program id and account metas of a failed CPI, then panics with
+/// the `panic-on-cpi-error` feature.
+#[cold]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+pub(crate) fn log_cpi_error(
+    program_id: &Pubkey,
+    account_metas: &[AccountMeta],
+    error: &ProgramError,
+) {
+    log(&format!("CPI to {program_id} failed: {error}"));
+    for (index, account_meta) in account_metas.iter().enumerate() {
+        log(&format!(
+            "  account {index}: {} (signer: {}, writable: {})",
+            account_meta.pubkey, account_meta.is_signer, account_meta.is_writable
+        ));
+    }
+
+    #[cfg(feature = "panic-on-cpi-error")]
+    panic!("CPI to {program_id} failed: {error}");
+}
@@ -0,0 +1,145 @@
+use core::mem::MaybeUninit;
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta,
+    program_error::ProgramError, pubkey::Pubkey,
+};
+
+use crate::checks;
+
+/// Maximum number of accounts for which [`invoke_with_masks`] synthesizes the
+/// account metas on the stack. Above this, they are heap allocated.
+pub const MASK_STACK_ACCOUNTS: usize = 16;
+
+/// Invokes `program_id` with one account meta per account info, in order,
+/// where bit `i` of `writable_mask` / `signer_mask` sets `is_writable` /
+/// `is_signer` of the `i`-th account meta.
+///
+/// This is meant for routers that compute the shape of a CPI from bits: no
+/// `Instruction` and no `Vec<AccountMeta>` are built, and for up to
+/// [`MASK_STACK_ACCOUNTS`] accounts nothing is heap allocated. The borrow
+/// check of [`invoke_signed`](crate::invoke_signed) is performed against the
+/// synthesized metas.
+///
+/// Returns [`ProgramError::InvalidArgument`] if more than 64 account infos
+/// are passed or if either mask has a bit set at or above
+/// `account_infos.len()`.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_with_masks(
+    program_id: &Pubkey,
+    account_infos: &[AccountInfo],
+    data: &[u8],
+    writable_mask: u64,
+    signer_mask: u64,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    if account_infos.len() <= MASK_STACK_ACCOUNTS {
+        let mut metas = [const { MaybeUninit::<AccountMeta>::uninit() }; MASK_STACK_ACCOUNTS];
+        let metas = metas_from_masks(account_infos, writable_mask, signer_mask, &mut metas)?;
+        invoke_with_metas(program_id, metas, data, account_infos, signers_seeds)
+    } else {
+        let mut metas = Vec::<AccountMeta>::with_capacity(account_infos.len());
+        let metas = metas_from_masks(
+            account_infos,
+            writable_mask,
+            signer_mask,
+            metas.spare_capacity_mut(),
+        )?;
+        invoke_with_metas(program_id, metas, data, account_infos, signers_seeds)
+    }
+}
+
+#[inline(always)]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+fn invoke_with_metas(
+    program_id: &Pubkey,
+    account_metas: &[AccountMeta],
+    data: &[u8],
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_accounts_len(account_metas)?;
+    checks::check_borrows(account_metas, account_infos)?;
+
+    crate::invoke_signed_parts_unchecked(
+        program_id,
+        account_metas,
+        data,
+        account_infos,
+        signers_seeds,
+    )
+}
+
+/// Writes one account meta per account info into `buffer`, which must be at
+/// least as long as `account_infos`, and returns the initialized prefix.
+#[inline(always)]
+fn metas_from_masks<'a>(
+    account_infos: &[AccountInfo],
+    writable_mask: u64,
+    signer_mask: u64,
+    buffer: &'a mut [MaybeUninit<AccountMeta>],
+) -> Result<&'a [AccountMeta], ProgramError> {
+    let len = account_infos.len();
+    if len > u64::BITS as usize {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let valid_bits = u64::MAX
+        .checked_shl(len as u32)
+        .map_or(u64::MAX, |high| !high);
+    if (writable_mask | signer_mask) & !valid_bits != 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    let buffer = &mut buffer[..len];
+    for (i, (slot, account_info)) in buffer.iter_mut().zip(account_infos.iter()).enumerate() {
+        slot.write(AccountMeta {
+            pubkey: *account_info.key,
+            is_signer: signer_mask & (1 << i) != 0,
+            is_writable: writable_mask & (1 << i) != 0,
+        });
+    }
+
+    // SAFETY: every element was initialized above
+    Ok(unsafe { &*(buffer as *const [MaybeUninit<AccountMeta>] as *const [AccountMeta]) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metas_from_masks() {
+        let owner = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let mut lamports = [0u64; 3];
+        let mut data = [[0u8; 0]; 3];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+
+        let mut buffer = [const { MaybeUninit::<AccountMeta>::uninit() }; MASK_STACK_ACCOUNTS];
+        let metas = metas_from_masks(&account_infos, 0b101, 0b001, &mut buffer).unwrap();
+        assert_eq!(
+            metas,
+            [
+                AccountMeta::new(keys[0], true),
+                AccountMeta::new_readonly(keys[1], false),
+                AccountMeta::new(keys[2], false),
+            ]
+        );
+
+        // Bits past the last account are rejected
+        assert_eq!(
+            metas_from_masks(&account_infos, 0b1000, 0, &mut buffer).unwrap_err(),
+            ProgramError::InvalidArgument
+        );
+        assert_eq!(
+            metas_from_masks(&account_infos, 0, 0b1000, &mut buffer).unwrap_err(),
+            ProgramError::InvalidArgument
+        );
+    }
+}
@@ -0,0 +1,534 @@
+//! An off-chain stand-in for the invoke syscall, for unit testing programs
+//! that CPI without spinning up a runtime.
+//!
+//! With the `mock` feature, every invoke function of this crate calls the
+//! current thread's [`MockHandler`] off-chain, after its checks, instead of
+//! panicking. Without a handler, every CPI succeeds without doing anything.
+//! On-chain, the syscall is always fired.
+//!
+//! Messages logged by handlers with [`log`] and by this crate (e.g. with the
+//! `log-errors` feature) are captured per thread, see [`take_logs`] and
+//! [`invoke_signed_capturing_logs`]. Off-chain, `msg!` and `sol_log` only
+//! print to stdout and can't be captured.
+//!
+//! [`set_mock_return_data`] simulates a callee setting return data, which
+//! this crate's [`get_return_data`](crate::get_return_data) and the other
+//! return data helpers then read, per thread.
+//!
+//! [`record_invocations`] records the instructions of this thread's CPIs,
+//! for tests to count or inspect with [`invocation_count`] and
+//! [`take_invocations`].
+//!
+//! [`assert_deterministic`] fires a CPI twice from the same starting state
+//! and panics if the two runs have different effects.
+//!
+//! For compute regression tests, [`set_simulated_compute`] starts metering
+//! this thread's CPIs: each one consumes what the runtime charges for it
+//! ([`INVOKE_UNITS`] plus one unit per [`CPI_BYTES_PER_UNIT`] bytes of
+//! instruction data), and handlers can consume the callee's own cost with
+//! [`consume_simulated_compute`]. Exceeding the budget panics, as the runtime
+//! would abort the transaction.
+
+use std::cell::{Cell, RefCell};
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+    program_error::ProgramError, pubkey::Pubkey,
+    stable_layout::stable_instruction::StableInstruction,
+};
+
+use crate::instruction_stabilizer::to_instruction;
+
+/// Stands in for the callee. It may mutate the account infos (lamports,
+/// data, reallocs) as the callee would.
+pub type MockHandler = fn(&Instruction, &[AccountInfo], &[&[&[u8]]]) -> ProgramResult;
+
+/// Compute units the runtime charges for every CPI, before the callee runs.
+pub const INVOKE_UNITS: u64 = 1000;
+
+/// Bytes of instruction data per compute unit the runtime charges for a CPI.
+pub const CPI_BYTES_PER_UNIT: u64 = 250;
+
+thread_local! {
+    static HANDLER: Cell<Option<MockHandler>> = const { Cell::new(None) };
+    static LOGS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static COMPUTE: Cell<Option<u64>> = const { Cell::new(None) };
+    static RETURN_DATA: RefCell<Option<(Pubkey, Vec<u8>)>> = const { RefCell::new(None) };
+    static RECORDED: RefCell<Option<Vec<Instruction>>> = const { RefCell::new(None) };
+    static ERROR_CODE: Cell<Option<u64>> = const { Cell::new(None) };
+}
+
+/// Sets the handler called in place of the syscall on this thread.
+pub fn set_mock_handler(handler: MockHandler) {
+    HANDLER.set(Some(handler));
+}
+
+/// Removes this thread's handler, so that CPIs succeed without doing
+/// anything again.
+pub fn clear_mock_handler() {
+    HANDLER.set(None);
+}
+
+/// Makes this thread's CPIs return the raw syscall `code` instead of calling
+/// the handler, e.g. to simulate a code that no [`ProgramError`] converts
+/// to.
+pub fn set_mock_error_code(code: u64) {
+    ERROR_CODE.set(Some(code));
+}
+
+/// Calls this thread's handler again in place of the syscall.
+pub fn clear_mock_error_code() {
+    ERROR_CODE.set(None);
+}
+
+/// Starts recording the instructions dispatched in place of the syscall on
+/// this thread (nested CPIs included, whether or not a handler is set),
+/// discarding any recorded so far.
+pub fn record_invocations() {
+    RECORDED.set(Some(Vec::new()));
+}
+
+/// The number of instructions recorded since [`record_invocations`].
+pub fn invocation_count() -> usize {
+    RECORDED.with_borrow(|recorded| recorded.as_ref().map_or(0, Vec::len))
+}
+
+/// Stops recording and returns the instructions recorded since
+/// [`record_invocations`], in the order they were dispatched.
+pub fn take_invocations() -> Vec<Instruction> {
+    RECORDED.take().unwrap_or_default()
+}
+
+/// Starts metering this thread's CPIs against a budget of `units`.
+pub fn set_simulated_compute(units: u64) {
+    COMPUTE.set(Some(units));
+}
+
+/// Stops metering this thread's CPIs.
+pub fn clear_simulated_compute() {
+    COMPUTE.set(None);
+}
+
+/// The remaining simulated compute units, or `None` if not metering.
+pub fn remaining_simulated_compute() -> Option<u64> {
+    COMPUTE.get()
+}
+
+/// Consumes simulated compute units, e.g. from a handler standing in for a
+/// callee's own cost. This is a no-op when not metering.
+///
+/// # Panics
+///
+/// Panics if fewer than `units` remain.
+#[track_caller]
+pub fn consume_simulated_compute(units: u64) {
+    if let Some(remaining) = COMPUTE.get() {
+        let Some(remaining) = remaining.checked_sub(units) else {
+            COMPUTE.set(Some(0));
+            panic!("exceeded the simulated compute budget: {units} units requested, {remaining} remaining");
+        };
+        COMPUTE.set(Some(remaining));
+    }
+}
+
+/// Sets the return data read by this thread, as if `program` had set `data`,
+/// e.g. from a handler standing in for an oracle.
+///
+/// Until [`clear_mock_return_data`], this crate's
+/// [`get_return_data`](crate::get_return_data) and return data helpers read
+/// it instead of going through `solana_program`'s `SyscallStubs`.
+pub fn set_mock_return_data(program: Pubkey, data: Vec<u8>) {
+    RETURN_DATA.set(Some((program, data)));
+}
+
+/// Removes this thread's mock return data, so that return data is read from
+/// the `SyscallStubs` again.
+pub fn clear_mock_return_data() {
+    RETURN_DATA.set(None);
+}
+
+pub(crate) fn mock_return_data() -> Option<(Pubkey, Vec<u8>)> {
+    RETURN_DATA.with_borrow(Clone::clone)
+}
+
+/// Logs a message from a handler, as the callee would with `msg!`. The
+/// message is printed and captured.
+pub fn log(message: &str) {
+    crate::log::log(message);
+}
+
+/// Returns and clears the messages logged on this thread.
+pub fn take_logs() -> Vec<String> {
+    LOGS.take()
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), returning the messages
+/// logged during the CPI, e.g. to assert that a callee emitted the expected
+/// events.
+///
+/// On success, the returned messages are removed from this thread's logs. On
+/// error, they are left there for [`take_logs`].
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_capturing_logs(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> Result<Vec<String>, ProgramError> {
+    let start = LOGS.with_borrow(Vec::len);
+    crate::invoke_signed(instruction, account_infos, signers_seeds)?;
+
+    Ok(LOGS.with_borrow_mut(|logs| logs.split_off(start)))
+}
+
+/// The state of an account, as compared by [`assert_deterministic`].
+#[derive(Debug, PartialEq)]
+struct AccountState {
+    key: Pubkey,
+    lamports: u64,
+    data: Vec<u8>,
+    owner: Pubkey,
+}
+
+impl AccountState {
+    fn snapshot(account_info: &AccountInfo) -> AccountState {
+        AccountState {
+            key: *account_info.key,
+            lamports: account_info.lamports(),
+            data: account_info.data.borrow().to_vec(),
+            owner: *account_info.owner,
+        }
+    }
+
+    /// Writes the state back into `account_info`, reallocating its data if
+    /// its length changed.
+    fn restore(&self, account_info: &AccountInfo) {
+        **account_info.lamports.borrow_mut() = self.lamports;
+        let mut data = account_info.data.borrow_mut();
+        if data.len() == self.data.len() {
+            data.copy_from_slice(&self.data);
+        } else {
+            // Leaked to outlive the account info, as account data does
+            *data = Box::leak(self.data.clone().into_boxed_slice());
+        }
+        account_info.assign(&self.owner);
+    }
+}
+
+/// Everything a CPI fired through the mock did, as compared by
+/// [`assert_deterministic`].
+#[derive(Debug, PartialEq)]
+struct Effects {
+    result: ProgramResult,
+    /// Every CPI dispatched to the handler, the outer one included
+    cpis: Vec<Instruction>,
+    accounts: Vec<AccountState>,
+    logs: Vec<String>,
+    return_data: Option<(Pubkey, Vec<u8>)>,
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), but fires the CPI twice
+/// from the same starting state and panics if the two runs had different
+/// effects, to guard a deterministic protocol against e.g. a hash map's
+/// iteration order deciding which account a handler borrows first.
+///
+/// The effects compared are the result, every CPI dispatched to the handler
+/// (including the ones it fires itself), the lamports, data and owner of
+/// every account info, the messages logged and the mock return data. Between
+/// the runs, the accounts, logs and return data are restored to what they
+/// were before the first one. Returns the result of the second run, leaving
+/// the state it produced.
+///
+/// # Panics
+///
+/// Panics if the two runs had different effects, or if an account's
+/// lamports or data are borrowed.
+#[track_caller]
+#[must_use = "the CPI may have failed"]
+pub fn assert_deterministic(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let accounts: Vec<AccountState> = account_infos.iter().map(AccountState::snapshot).collect();
+    let logs = LOGS.take();
+    let return_data = mock_return_data();
+    let recording = RECORDED.take();
+
+    let run = || {
+        for (state, account_info) in accounts.iter().zip(account_infos) {
+            state.restore(account_info);
+        }
+        LOGS.set(logs.clone());
+        RETURN_DATA.set(return_data.clone());
+        record_invocations();
+        let result = crate::invoke_signed(instruction, account_infos, signers_seeds);
+        Effects {
+            result,
+            cpis: take_invocations(),
+            accounts: account_infos.iter().map(AccountState::snapshot).collect(),
+            logs: LOGS.with_borrow(|run_logs| run_logs[logs.len()..].to_vec()),
+            return_data: mock_return_data(),
+        }
+    };
+    let first = run();
+    let second = run();
+    // Resume the caller's recording, with the CPIs of the second run
+    if let Some(mut recorded) = recording {
+        recorded.extend(second.cpis.iter().cloned());
+        RECORDED.set(Some(recorded));
+    }
+    assert_eq!(first, second, "the CPI is not deterministic");
+
+    second.result
+}
+
+pub(crate) fn capture_log(message: &str) {
+    LOGS.with_borrow_mut(|logs| logs.push(message.to_string()));
+}
+
+/// Dispatches a stabilized instruction to the current handler, returning the
+/// code the syscall would have returned.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+pub(crate) fn invoke(
+    instruction: &StableInstruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> u64 {
+    consume_simulated_compute(INVOKE_UNITS + instruction.data.len() / CPI_BYTES_PER_UNIT);
+
+    let instruction = to_instruction(instruction);
+    RECORDED.with_borrow_mut(|recorded| {
+        if let Some(recorded) = recorded {
+            recorded.push(instruction.clone());
+        }
+    });
+    if let Some(code) = ERROR_CODE.get() {
+        return code;
+    }
+    let result = match HANDLER.get() {
+        Some(handler) => handler(&instruction, account_infos, signers_seeds),
+        None => Ok(()),
+    };
+    match result {
+        Ok(()) => solana_program::entrypoint::SUCCESS,
+        Err(error) => error.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::{instruction::AccountMeta, pubkey::Pubkey};
+
+    use super::*;
+
+    // Moves 1 lamport from the first to the second account meta
+    fn transfer(
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let [from, to] = [0, 1].map(|i| {
+            account_infos
+                .iter()
+                .find(|info| *info.key == instruction.accounts[i].pubkey)
+                .unwrap()
+        });
+        **from.try_borrow_mut_lamports()? -= 1;
+        **to.try_borrow_mut_lamports()? += 1;
+        Ok(())
+    }
+
+    #[test]
+    fn test_mock_handler() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [10u64; 2];
+        let mut data = [[0u8; 0]; 2];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new(keys[1], false),
+            ],
+            data: vec![],
+        };
+
+        // No handler
+        assert_eq!(crate::invoke(&instruction, &account_infos), Ok(()));
+        assert_eq!(account_infos[0].lamports(), 10);
+
+        set_mock_handler(transfer);
+        assert_eq!(crate::invoke(&instruction, &account_infos), Ok(()));
+        assert_eq!(
+            crate::invoke_signed_c(&instruction, &account_infos, &[]),
+            Ok(())
+        );
+        assert_eq!(account_infos[0].lamports(), 8);
+        assert_eq!(account_infos[1].lamports(), 12);
+
+        if !cfg!(feature = "panic-on-cpi-error") {
+            set_mock_handler(|_, _, _| Err(ProgramError::Custom(7)));
+            assert_eq!(
+                crate::invoke(&instruction, &account_infos),
+                Err(ProgramError::Custom(7))
+            );
+        }
+
+        clear_mock_handler();
+        assert_eq!(crate::invoke(&instruction, &account_infos), Ok(()));
+    }
+
+    #[test]
+    fn test_invoke_signed_capturing_logs() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![2],
+        };
+
+        set_mock_handler(|instruction, _, _| {
+            for i in 0..instruction.data[0] {
+                log(&format!("event {i}"));
+            }
+            if instruction.data.len() > 1 {
+                return Err(ProgramError::InvalidInstructionData);
+            }
+            Ok(())
+        });
+        log("before");
+        let logs = invoke_signed_capturing_logs(&instruction, &[], &[]);
+        let mut expected = vec![];
+        if cfg!(feature = "structured-logs") {
+            expected.push(format!("CPI|{}|0|1", instruction.program_id));
+        }
+        expected.extend(["event 0".to_string(), "event 1".to_string()]);
+        assert_eq!(logs.unwrap(), expected);
+        assert_eq!(take_logs(), ["before"]);
+
+        // On error, the logs stay in the thread's logs
+        if cfg!(feature = "panic-on-cpi-error") {
+            clear_mock_handler();
+            return;
+        }
+        let instruction = Instruction {
+            data: vec![1, 0],
+            ..instruction
+        };
+        let logs = invoke_signed_capturing_logs(&instruction, &[], &[]);
+        clear_mock_handler();
+        assert_eq!(logs, Err(ProgramError::InvalidInstructionData));
+        let mut expected = vec![];
+        if cfg!(feature = "structured-logs") {
+            expected.push(format!("CPI|{}|0|2", instruction.program_id));
+        }
+        expected.push("event 0".to_string());
+        assert!(take_logs().starts_with(&expected));
+    }
+
+    #[test]
+    fn test_simulated_compute() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![0; 600],
+        };
+
+        assert_eq!(remaining_simulated_compute(), None);
+        set_simulated_compute(4_000);
+        set_mock_handler(|_, _, _| {
+            consume_simulated_compute(500);
+            Ok(())
+        });
+        assert_eq!(crate::invoke(&instruction, &[]), Ok(()));
+        // 1000 to invoke, 2 for 600 bytes of data, 500 in the callee
+        assert_eq!(remaining_simulated_compute(), Some(4_000 - 1_502));
+        assert_eq!(crate::invoke(&instruction, &[]), Ok(()));
+        assert_eq!(remaining_simulated_compute(), Some(4_000 - 2 * 1_502));
+
+        // Out of budget on the third CPI
+        let result = std::panic::catch_unwind(|| crate::invoke(&instruction, &[]));
+        clear_mock_handler();
+        assert!(result.is_err());
+        assert_eq!(remaining_simulated_compute(), Some(0));
+
+        clear_simulated_compute();
+        assert_eq!(remaining_simulated_compute(), None);
+    }
+
+    #[test]
+    fn test_mock_return_data() {
+        let oracle = Pubkey::new_unique();
+        let price = 42u64.to_le_bytes();
+        let instruction = Instruction {
+            program_id: oracle,
+            accounts: vec![],
+            data: vec![],
+        };
+        // No stubs must set return data while this test falls back to them
+        let _lock = crate::STUBS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+
+        set_mock_handler(|instruction, _, _| {
+            set_mock_return_data(instruction.program_id, 42u64.to_le_bytes().to_vec());
+            Ok(())
+        });
+        let mut out = vec![];
+        let captured = crate::invoke_capturing(&instruction, &[], &mut out);
+        clear_mock_handler();
+        assert_eq!(captured, Ok(oracle));
+        assert_eq!(out, price);
+
+        assert_eq!(crate::get_return_data(), Some((oracle, price.to_vec())));
+        assert_eq!(crate::get_return_data_fixed::<8>(), Some((oracle, price)));
+        assert_eq!(crate::get_return_data_fixed::<4>(), None);
+
+        clear_mock_return_data();
+        assert_eq!(crate::get_return_data(), None);
+        assert_eq!(crate::get_return_data_fixed::<8>(), None);
+    }
+
+    #[test]
+    fn test_assert_deterministic() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let mut lamports = [10u64; 2];
+        let mut data = [[0u8; 4]; 2];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new(keys[1], false),
+            ],
+            data: vec![],
+        };
+
+        // Both runs start from 10 lamports each, and only the second sticks
+        set_mock_handler(transfer);
+        assert_eq!(
+            assert_deterministic(&instruction, &account_infos, &[]),
+            Ok(())
+        );
+        assert_eq!(account_infos[0].lamports(), 9);
+        assert_eq!(account_infos[1].lamports(), 11);
+
+        // Effects depending on anything but the starting state
+        thread_local! {
+            static RUNS: Cell<u8> = const { Cell::new(0) };
+        }
+        set_mock_handler(|_, account_infos, _| {
+            RUNS.set(RUNS.get() + 1);
+            account_infos[1].try_borrow_mut_data()?[0] = RUNS.get();
+            Ok(())
+        });
+        let nondeterministic = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = assert_deterministic(&instruction, &account_infos, &[]);
+        }));
+        clear_mock_handler();
+        assert!(nondeterministic.is_err());
+        assert_eq!(RUNS.get(), 2);
+    }
+}
@@ -0,0 +1,297 @@
+use solana_program::{
+    account_info::AccountInfo,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+use crate::checks::find_account_info;
+
+/// Clones the account infos into the order of `instruction.accounts`.
+///
+/// Duplicate account metas produce duplicate account infos. If an account
+/// info for the instruction's program id was passed in, it is appended after
+/// the ordered infos. Cloning an `AccountInfo` only bumps the `Rc` counts, so
+/// the returned infos share their lamports and data `RefCell`s with the
+/// originals.
+///
+/// Returns [`ProgramError::NotEnoughAccountKeys`] if any account meta has no
+/// matching account info.
+pub(crate) fn order_account_infos<'info>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo<'info>],
+) -> Result<Vec<AccountInfo<'info>>, ProgramError> {
+    let mut ordered = Vec::with_capacity(instruction.accounts.len() + 1);
+    for account_meta in instruction.accounts.iter() {
+        let account_info = find_account_info(account_meta, account_infos)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        ordered.push(account_info.clone());
+    }
+    if let Some(program_info) = account_infos
+        .iter()
+        .find(|account_info| *account_info.key == instruction.program_id)
+    {
+        ordered.push(program_info.clone());
+    }
+
+    Ok(ordered)
+}
+
+/// Concatenates two slices of account infos into one, e.g. to pass account
+/// infos held in different slices by a program handling nested instructions
+/// to a single CPI.
+///
+/// Cloning an `AccountInfo` only bumps the `Rc` counts of its lamports and
+/// data, so the merged infos share their `RefCell`s with the originals: the
+/// borrow check sees borrows held through either, and changes made by the
+/// callee are visible through both. Both slices must have the same `'info`
+/// lifetime (the lifetime is invariant), which all account infos deserialized
+/// from one entrypoint input have.
+///
+/// Account infos present in both slices are kept twice, which the runtime
+/// accepts: the first account info matching each account meta is used.
+pub fn merge_infos<'info>(
+    a: &[AccountInfo<'info>],
+    b: &[AccountInfo<'info>],
+) -> Vec<AccountInfo<'info>> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    merged.extend_from_slice(a);
+    merged.extend_from_slice(b);
+    merged
+}
+
+/// Sorts the signer seed groups by the PDA each derives for `program_id`.
+///
+/// Whole groups are moved, so each keeps its seeds and bump, and the sort is
+/// stable, so groups deriving the same PDA stay in their relative order. The
+/// result only depends on the set of groups, not on their original order.
+///
+/// Returns [`ProgramError::InvalidSeeds`] if a group doesn't derive a PDA.
+pub(crate) fn sort_signers_seeds<'a>(
+    signers_seeds: &[&'a [&'a [u8]]],
+    program_id: &Pubkey,
+) -> Result<Vec<&'a [&'a [u8]]>, ProgramError> {
+    let mut keyed = signers_seeds
+        .iter()
+        .map(|seeds| Ok((Pubkey::create_program_address(seeds, program_id)?, *seeds)))
+        .collect::<Result<Vec<_>, ProgramError>>()
+        .map_err(|_| ProgramError::InvalidSeeds)?;
+    keyed.sort_by_key(|(pda, _)| *pda);
+
+    Ok(keyed.into_iter().map(|(_, seeds)| seeds).collect())
+}
+
+/// Sorts a copy of `instruction.accounts` by pubkey and clones the matching
+/// account infos into the same order.
+///
+/// The sort is stable and moves whole account metas, so each keeps its own
+/// signer and writable flags, and duplicates of a key stay in their original
+/// relative order. The result only depends on the account metas, not on the
+/// order of `account_infos`.
+///
+/// Returns [`ProgramError::NotEnoughAccountKeys`] if any account meta has no
+/// matching account info.
+pub(crate) fn sort_account_metas<'info>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo<'info>],
+) -> Result<(Vec<AccountMeta>, Vec<AccountInfo<'info>>), ProgramError> {
+    let mut account_metas = instruction.accounts.clone();
+    account_metas.sort_by_key(|account_meta| account_meta.pubkey);
+    let sorted_infos = account_metas
+        .iter()
+        .map(|account_meta| {
+            find_account_info(account_meta, account_infos)
+                .cloned()
+                .ok_or(ProgramError::NotEnoughAccountKeys)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok((account_metas, sorted_infos))
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::{instruction::AccountMeta, pubkey::Pubkey};
+
+    use super::*;
+
+    #[test]
+    fn test_order_account_infos() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let keys = [
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        ];
+        let mut lamports = [0u64; 4];
+        let mut data = [[0u8; 0]; 4];
+        let all_keys = [keys[0], keys[1], keys[2], program_id];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&all_keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new(keys[1], false),
+                AccountMeta::new(keys[2], false),
+                AccountMeta::new_readonly(keys[0], false),
+            ],
+            data: vec![],
+        };
+
+        // Deliberately shuffled
+        let shuffled = [
+            account_infos[2].clone(),
+            account_infos[3].clone(),
+            account_infos[0].clone(),
+            account_infos[1].clone(),
+        ];
+        let ordered = order_account_infos(&instruction, &shuffled).unwrap();
+        let ordered_keys: Vec<Pubkey> = ordered.iter().map(|info| *info.key).collect();
+        assert_eq!(ordered_keys, [keys[1], keys[2], keys[0], program_id]);
+
+        // Missing key
+        assert_eq!(
+            order_account_infos(&instruction, &shuffled[..3]).unwrap_err(),
+            ProgramError::NotEnoughAccountKeys
+        );
+    }
+
+    #[test]
+    fn test_sort_signers_seeds() {
+        let program_id = Pubkey::new_unique();
+        let bumps: Vec<u8> = [b"a", b"b", b"c"]
+            .iter()
+            .map(|seed| Pubkey::find_program_address(&[*seed], &program_id).1)
+            .collect();
+        let groups: Vec<[&[u8]; 2]> = [b"a", b"b", b"c"]
+            .iter()
+            .zip(bumps.iter())
+            .map(|(seed, bump)| [&seed[..], core::slice::from_ref(bump)])
+            .collect();
+        let signers_seeds: Vec<&[&[u8]]> = groups.iter().map(|group| &group[..]).collect();
+
+        let sorted = sort_signers_seeds(&signers_seeds, &program_id).unwrap();
+        let pdas: Vec<Pubkey> = sorted
+            .iter()
+            .map(|seeds| Pubkey::create_program_address(seeds, &program_id).unwrap())
+            .collect();
+        assert!(pdas.is_sorted());
+        // Each group keeps its own bump
+        for seeds in &sorted {
+            let index = signers_seeds.iter().position(|s| s == seeds).unwrap();
+            assert_eq!(seeds[1], [bumps[index]]);
+        }
+        // The same result from any starting order
+        let reversed: Vec<&[&[u8]]> = signers_seeds.iter().rev().copied().collect();
+        assert_eq!(sort_signers_seeds(&reversed, &program_id).unwrap(), sorted);
+
+        let invalid: &[&[u8]] = &[b"a", &[bumps[0].wrapping_add(1)]];
+        if Pubkey::create_program_address(invalid, &program_id).is_err() {
+            assert_eq!(
+                sort_signers_seeds(&[invalid], &program_id),
+                Err(ProgramError::InvalidSeeds)
+            );
+        }
+    }
+
+    #[test]
+    fn test_sort_account_metas() {
+        let program_id = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [0u64; 4];
+        let mut data = [[0u8; 0]; 4];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new(keys[3], true),
+                AccountMeta::new_readonly(keys[0], false),
+                AccountMeta::new_readonly(keys[2], true),
+                AccountMeta::new(keys[1], false),
+            ],
+            data: vec![],
+        };
+
+        let (account_metas, sorted_infos) =
+            sort_account_metas(&instruction, &account_infos).unwrap();
+        let mut expected = instruction.accounts.clone();
+        expected.sort_by_key(|account_meta| account_meta.pubkey);
+        assert_eq!(account_metas, expected);
+        assert!(account_metas.windows(2).all(|w| w[0].pubkey <= w[1].pubkey));
+        for (account_meta, account_info) in account_metas.iter().zip(&sorted_infos) {
+            assert_eq!(account_meta.pubkey, *account_info.key);
+        }
+
+        // Deterministic regardless of the order of the account metas or infos
+        let shuffled = Instruction {
+            accounts: [2, 0, 3, 1]
+                .map(|i| instruction.accounts[i].clone())
+                .to_vec(),
+            ..instruction.clone()
+        };
+        let reversed: Vec<AccountInfo> = account_infos.iter().rev().cloned().collect();
+        let (shuffled_metas, shuffled_infos) = sort_account_metas(&shuffled, &reversed).unwrap();
+        assert_eq!(shuffled_metas, account_metas);
+        let keys_of =
+            |infos: &[AccountInfo]| infos.iter().map(|info| *info.key).collect::<Vec<_>>();
+        assert_eq!(keys_of(&shuffled_infos), keys_of(&sorted_infos));
+
+        // Missing key
+        assert_eq!(
+            sort_account_metas(&instruction, &account_infos[1..]).unwrap_err(),
+            ProgramError::NotEnoughAccountKeys
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_merge_infos() {
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [10u64; 3];
+        let mut data = [[0u8; 0]; 3];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let (a, b) = account_infos.split_at(1);
+
+        let merged = merge_infos(a, b);
+        let merged_keys: Vec<Pubkey> = merged.iter().map(|info| *info.key).collect();
+        assert_eq!(merged_keys, keys);
+
+        // A transfer from the account of `a` to the last account of `b`
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new(keys[2], false),
+            ],
+            data: vec![],
+        };
+        crate::mock::set_mock_handler(|instruction, account_infos, _| {
+            let [from, to] =
+                [0, 1].map(|i| find_account_info(&instruction.accounts[i], account_infos).unwrap());
+            **from.try_borrow_mut_lamports()? -= 1;
+            **to.try_borrow_mut_lamports()? += 1;
+            Ok(())
+        });
+        let result = crate::invoke(&instruction, &merged);
+        crate::mock::clear_mock_handler();
+        assert_eq!(result, Ok(()));
+
+        // Visible through the original slices
+        assert_eq!(a[0].lamports(), 9);
+        assert_eq!(b[1].lamports(), 11);
+
+        // Borrows held through the originals are seen by the borrow check
+        if !crate::checks::NO_BORROW_CHECK {
+            let _guard = b[1].try_borrow_data().unwrap();
+            assert_eq!(
+                crate::invoke(&instruction, &merged),
+                Err(ProgramError::AccountBorrowFailed)
+            );
+        }
+    }
+}
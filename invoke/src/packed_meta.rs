@@ -0,0 +1,106 @@
+use solana_program::{instruction::AccountMeta, program_error::ProgramError, pubkey::Pubkey};
+
+/// An account meta as stored in account data, layout-compatible with
+/// [`AccountMeta`] so that a slice of them can be viewed as account metas
+/// without copying, see [`from_packed`].
+///
+/// The layout is exactly 34 bytes with an alignment of 1, and no padding:
+///
+/// | offset | size | field         | valid values |
+/// |--------|------|---------------|--------------|
+/// | 0      | 32   | `pubkey`      | any          |
+/// | 32     | 1    | `is_signer`   | 0 or 1       |
+/// | 33     | 1    | `is_writable` | 0 or 1       |
+///
+/// Since the alignment is 1, a slice of them can be cast from any byte slice
+/// whose length is a multiple of 34. The flags are bytes rather than `bool`s
+/// so that such a cast is always sound; [`from_packed`] validates them.
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PackedAccountMeta {
+    pub pubkey: Pubkey,
+    pub is_signer: u8,
+    pub is_writable: u8,
+}
+
+const _: () = {
+    use core::mem::{align_of, offset_of, size_of};
+
+    assert!(size_of::<PackedAccountMeta>() == 34);
+    assert!(size_of::<PackedAccountMeta>() == size_of::<AccountMeta>());
+    assert!(align_of::<PackedAccountMeta>() == 1);
+    assert!(align_of::<PackedAccountMeta>() == align_of::<AccountMeta>());
+    assert!(offset_of!(PackedAccountMeta, pubkey) == offset_of!(AccountMeta, pubkey));
+    assert!(offset_of!(PackedAccountMeta, is_signer) == offset_of!(AccountMeta, is_signer));
+    assert!(offset_of!(PackedAccountMeta, is_writable) == offset_of!(AccountMeta, is_writable));
+};
+
+impl From<&AccountMeta> for PackedAccountMeta {
+    fn from(account_meta: &AccountMeta) -> PackedAccountMeta {
+        PackedAccountMeta {
+            pubkey: account_meta.pubkey,
+            is_signer: account_meta.is_signer as u8,
+            is_writable: account_meta.is_writable as u8,
+        }
+    }
+}
+
+/// Views packed account metas as account metas, without copying, e.g. to
+/// pass account lists stored in account state to
+/// [`invoke_signed_vec_like`](crate::invoke_signed_vec_like).
+///
+/// Returns [`ProgramError::InvalidAccountData`] if any flag byte is neither 0
+/// nor 1, since those are the only valid `bool`s.
+pub fn from_packed(packed: &[PackedAccountMeta]) -> Result<&[AccountMeta], ProgramError> {
+    if packed
+        .iter()
+        .any(|meta| meta.is_signer > 1 || meta.is_writable > 1)
+    {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    // SAFETY: the layouts are asserted identical above, and every flag byte
+    // is a valid `bool`
+    Ok(unsafe { core::slice::from_raw_parts(packed.as_ptr() as *const AccountMeta, packed.len()) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_packed() {
+        let account_metas = vec![
+            AccountMeta::new(Pubkey::new_unique(), true),
+            AccountMeta::new(Pubkey::new_unique(), false),
+            AccountMeta::new_readonly(Pubkey::new_unique(), true),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+        ];
+
+        let mut packed: Vec<PackedAccountMeta> =
+            account_metas.iter().map(PackedAccountMeta::from).collect();
+        let unpacked = from_packed(&packed).unwrap();
+        assert_eq!(unpacked, account_metas);
+        assert_eq!(unpacked.as_ptr() as *const u8, packed.as_ptr() as *const u8);
+
+        // Stabilizing views the packed metas in place
+        let data: &[u8] = &[];
+        let stabilizer = crate::instruction_stabilizer::InstructionStabilizer::stabilize_vec_like(
+            &Pubkey::new_unique(),
+            &unpacked,
+            &data,
+        );
+        let stable = stabilizer.stable_instruction_ref();
+        assert_eq!(&stable.accounts[..], &account_metas[..]);
+        assert_eq!(
+            stable.accounts.as_ptr() as *const u8,
+            packed.as_ptr() as *const u8
+        );
+
+        // Invalid flags
+        packed[1].is_writable = 2;
+        assert_eq!(from_packed(&packed), Err(ProgramError::InvalidAccountData));
+
+        assert_eq!(from_packed(&[]), Ok(&[][..]));
+    }
+}
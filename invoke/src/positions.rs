@@ -0,0 +1,188 @@
+use core::mem::MaybeUninit;
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta, pubkey::Pubkey,
+};
+
+use crate::checks;
+
+/// Maximum number of positions for which [`invoke_with_positions`] builds
+/// the account metas and account infos on the stack. Above this, they are
+/// heap allocated.
+pub const POSITIONS_STACK_ACCOUNTS: usize = 16;
+
+/// Invokes `program_id` with one account meta per position, taking its key,
+/// `is_signer` and `is_writable` from the account info at that position.
+///
+/// The same account info may appear at several positions, as some programs
+/// (e.g. AMMs) require. The account infos are copied into one contiguous
+/// slice in position order, duplicates included, without touching their
+/// `Rc` counts. For a PDA signed for with `signers_seeds`, pass a clone of
+/// its account info with `is_signer` set.
+///
+/// Nothing is heap allocated for up to [`POSITIONS_STACK_ACCOUNTS`]
+/// positions. The borrow check of [`invoke_signed`](crate::invoke_signed) is
+/// performed against the synthesized metas.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_with_positions(
+    program_id: &Pubkey,
+    infos_by_position: &[&AccountInfo],
+    data: &[u8],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    if infos_by_position.len() <= POSITIONS_STACK_ACCOUNTS {
+        let mut metas = [const { MaybeUninit::<AccountMeta>::uninit() }; POSITIONS_STACK_ACCOUNTS];
+        let mut infos = [const { MaybeUninit::<AccountInfo>::uninit() }; POSITIONS_STACK_ACCOUNTS];
+        let (metas, infos) = layout(infos_by_position, &mut metas, &mut infos);
+        invoke_with_layout(program_id, metas, data, infos, signers_seeds)
+    } else {
+        let mut metas = Vec::<AccountMeta>::with_capacity(infos_by_position.len());
+        let mut infos = Vec::<AccountInfo>::with_capacity(infos_by_position.len());
+        let (metas, infos) = layout(
+            infos_by_position,
+            metas.spare_capacity_mut(),
+            infos.spare_capacity_mut(),
+        );
+        invoke_with_layout(program_id, metas, data, infos, signers_seeds)
+    }
+}
+
+#[inline(always)]
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+fn invoke_with_layout(
+    program_id: &Pubkey,
+    account_metas: &[AccountMeta],
+    data: &[u8],
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_accounts_len(account_metas)?;
+    checks::check_borrows(account_metas, account_infos)?;
+
+    crate::invoke_signed_parts_unchecked(
+        program_id,
+        account_metas,
+        data,
+        account_infos,
+        signers_seeds,
+    )
+}
+
+/// Writes one account meta and one bitwise copy of the account info per
+/// position into the buffers, which must be at least as long as
+/// `infos_by_position`, and returns the initialized prefixes.
+///
+/// The copies are never dropped, so the `Rc`s they share with the originals
+/// are never decremented.
+#[inline(always)]
+fn layout<'a, 'info>(
+    infos_by_position: &[&AccountInfo<'info>],
+    metas: &'a mut [MaybeUninit<AccountMeta>],
+    infos: &'a mut [MaybeUninit<AccountInfo<'info>>],
+) -> (&'a [AccountMeta], &'a [AccountInfo<'info>]) {
+    let len = infos_by_position.len();
+    let (metas, infos) = (&mut metas[..len], &mut infos[..len]);
+    for ((meta, info), account_info) in metas
+        .iter_mut()
+        .zip(infos.iter_mut())
+        .zip(infos_by_position.iter())
+    {
+        meta.write(AccountMeta {
+            pubkey: *account_info.key,
+            is_signer: account_info.is_signer,
+            is_writable: account_info.is_writable,
+        });
+        // SAFETY: the copy is only ever read, and never dropped
+        info.write(unsafe { core::ptr::read(*account_info) });
+    }
+    // SAFETY: every element was initialized above, and `MaybeUninit<T>` is
+    // layout-compatible with `T`
+    unsafe {
+        (
+            &*(metas as *const [MaybeUninit<AccountMeta>] as *const [AccountMeta]),
+            &*(infos as *const [MaybeUninit<AccountInfo>] as *const [AccountInfo]),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_layout() {
+        let owner = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique()];
+        let (mut lamports_a, mut lamports_b) = (0, 0);
+        let (mut data_a, mut data_b) = ([0u8; 0], [0u8; 0]);
+        let a = AccountInfo::new(
+            &keys[0],
+            true,
+            true,
+            &mut lamports_a,
+            &mut data_a,
+            &owner,
+            false,
+            0,
+        );
+        let b = AccountInfo::new(
+            &keys[1],
+            false,
+            false,
+            &mut lamports_b,
+            &mut data_b,
+            &owner,
+            false,
+            0,
+        );
+
+        let mut metas = [const { MaybeUninit::<AccountMeta>::uninit() }; 3];
+        let mut infos = [const { MaybeUninit::<AccountInfo>::uninit() }; 3];
+        let (metas, infos) = layout(&[&a, &b, &a], &mut metas, &mut infos);
+        assert_eq!(
+            metas,
+            [
+                AccountMeta::new(keys[0], true),
+                AccountMeta::new_readonly(keys[1], false),
+                AccountMeta::new(keys[0], true),
+            ]
+        );
+        let info_keys: Vec<Pubkey> = infos.iter().map(|info| *info.key).collect();
+        assert_eq!(info_keys, [keys[0], keys[1], keys[0]]);
+        // The copies share their `RefCell`s with the originals
+        assert!(core::ptr::eq(infos[2].data.as_ptr(), a.data.as_ptr()));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_with_positions() {
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let (mut lamports, mut data) = (10, [0u8; 0]);
+        let pool = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+
+        // The callee sees the same account at both positions
+        crate::mock::set_mock_handler(|instruction, account_infos, _| {
+            assert_eq!(instruction.accounts.len(), 2);
+            assert_eq!(instruction.accounts[0], instruction.accounts[1]);
+            assert_eq!(account_infos.len(), 2);
+            **account_infos[1].try_borrow_mut_lamports()? += 1;
+            Ok(())
+        });
+        let result = invoke_with_positions(&Pubkey::new_unique(), &[&pool, &pool], &[], &[]);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(pool.lamports(), 11);
+    }
+}
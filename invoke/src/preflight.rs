@@ -0,0 +1,182 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+};
+
+use crate::checks;
+
+/// The validation performed before firing a CPI with [`invoke_with_check`].
+///
+/// Implementations are zero-sized markers chosen per call, so the checks are
+/// resolved at compile time and [`NoCheck`] costs nothing.
+pub trait PreflightCheck {
+    fn check(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult;
+}
+
+/// The checks of [`invoke_signed`](crate::invoke_signed): the account metas
+/// limit and the borrow check.
+pub struct StandardCheck;
+
+impl PreflightCheck for StandardCheck {
+    #[inline(always)]
+    fn check(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+        checks::check_accounts_len(&instruction.accounts)?;
+        checks::check_borrows(&instruction.accounts, account_infos)
+    }
+}
+
+/// No checks, as in [`invoke_signed_unchecked`](crate::invoke_signed_unchecked).
+pub struct NoCheck;
+
+impl PreflightCheck for NoCheck {
+    #[inline(always)]
+    fn check(_instruction: &Instruction, _account_infos: &[AccountInfo]) -> ProgramResult {
+        Ok(())
+    }
+}
+
+/// [`StandardCheck`], plus:
+///
+/// - every account meta has a matching account info
+///   ([`ProgramError::NotEnoughAccountKeys`] otherwise),
+/// - writable account metas match writable account infos
+///   ([`ProgramError::InvalidArgument`] otherwise),
+/// - the program account is passed and executable
+///   ([`ProgramError::InvalidAccountData`] otherwise).
+///
+/// [`ProgramError::NotEnoughAccountKeys`]: solana_program::program_error::ProgramError::NotEnoughAccountKeys
+/// [`ProgramError::InvalidArgument`]: solana_program::program_error::ProgramError::InvalidArgument
+/// [`ProgramError::InvalidAccountData`]: solana_program::program_error::ProgramError::InvalidAccountData
+pub struct StrictCheck;
+
+impl PreflightCheck for StrictCheck {
+    #[inline(always)]
+    fn check(instruction: &Instruction, account_infos: &[AccountInfo]) -> ProgramResult {
+        checks::check_accounts(instruction, account_infos)?;
+        checks::check_executable(&instruction.program_id, account_infos)?;
+        StandardCheck::check(instruction, account_infos)
+    }
+}
+
+/// Same as [`invoke_signed_unchecked`](crate::invoke_signed_unchecked), after
+/// the pre-flight checks of `C`, e.g. `invoke_with_check::<StrictCheck>`.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_with_check<C: PreflightCheck>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    C::check(instruction, account_infos)?;
+
+    crate::invoke_signed_unchecked(instruction, account_infos, signers_seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::{instruction::AccountMeta, program_error::ProgramError, pubkey::Pubkey};
+
+    use super::*;
+
+    #[test]
+    fn test_preflight_checks() {
+        let owner = Pubkey::new_unique();
+        let program_id = Pubkey::new_unique();
+        let keys = [Pubkey::new_unique(), Pubkey::new_unique(), program_id];
+        let mut lamports = [0u64; 3];
+        let mut data = [[0u8; 0]; 3];
+        let executable = [false, false, true];
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .zip(executable.iter())
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .map(|(((key, executable), lamports), data)| {
+                AccountInfo::new(key, false, true, lamports, data, &owner, *executable, 0)
+            })
+            .collect();
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new(keys[0], false),
+                AccountMeta::new_readonly(keys[1], false),
+            ],
+            data: vec![],
+        };
+
+        for result in [
+            NoCheck::check(&instruction, &account_infos),
+            StandardCheck::check(&instruction, &account_infos),
+            StrictCheck::check(&instruction, &account_infos),
+        ] {
+            assert_eq!(result, Ok(()));
+        }
+
+        // A live borrow fails the standard and strict checks only
+        {
+            let _guard = account_infos[0].try_borrow_data().unwrap();
+            assert_eq!(NoCheck::check(&instruction, &account_infos), Ok(()));
+            if !checks::NO_BORROW_CHECK {
+                assert_eq!(
+                    StandardCheck::check(&instruction, &account_infos),
+                    Err(ProgramError::AccountBorrowFailed)
+                );
+                assert_eq!(
+                    StrictCheck::check(&instruction, &account_infos),
+                    Err(ProgramError::AccountBorrowFailed)
+                );
+            }
+        }
+
+        // Missing account info, which only the strict check requires (and
+        // the standard check with the `strict` feature, as it is writable)
+        let missing = &account_infos[1..];
+        let standard = if cfg!(feature = "strict") {
+            Err(ProgramError::NotEnoughAccountKeys)
+        } else {
+            Ok(())
+        };
+        assert_eq!(StandardCheck::check(&instruction, missing), standard);
+        assert_eq!(
+            StrictCheck::check(&instruction, missing),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+
+        // Missing or non-executable program account
+        assert_eq!(
+            StrictCheck::check(&instruction, &account_infos[..2]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        let not_executable = Instruction {
+            program_id: keys[1],
+            ..instruction.clone()
+        };
+        assert_eq!(
+            StandardCheck::check(&not_executable, &account_infos),
+            Ok(())
+        );
+        assert_eq!(
+            StrictCheck::check(&not_executable, &account_infos),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_with_check() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![],
+        };
+
+        assert_eq!(invoke_with_check::<NoCheck>(&instruction, &[], &[]), Ok(()));
+        assert_eq!(
+            invoke_with_check::<StandardCheck>(&instruction, &[], &[]),
+            Ok(())
+        );
+        assert_eq!(
+            invoke_with_check::<StrictCheck>(&instruction, &[], &[]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+    }
+}
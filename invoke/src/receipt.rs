@@ -0,0 +1,89 @@
+use solana_program::{
+    account_info::AccountInfo, instruction::Instruction, program_error::ProgramError,
+    pubkey::Pubkey,
+};
+
+/// A summary of a CPI fired with [`invoke_with_receipt`], for logging or
+/// assertions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvokeReceipt {
+    /// The callee
+    pub program_id: Pubkey,
+    /// The number of account metas of the instruction
+    pub accounts_len: usize,
+    /// The length of the instruction data
+    pub data_len: usize,
+    /// The compute units consumed by the CPI, including what the runtime
+    /// charges for it and the callee's own consumption. Off-chain, these are
+    /// the `mock` feature's simulated units, or 0 when not metering.
+    pub compute_units: u64,
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), returning an
+/// [`InvokeReceipt`] of the CPI on success.
+///
+/// The compute units are measured with `sol_remaining_compute_units` right
+/// before and after the CPI, so they include the cost of one such call.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_with_receipt(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> Result<InvokeReceipt, ProgramError> {
+    let before = crate::remaining_compute_units();
+    crate::invoke_signed(instruction, account_infos, signers_seeds)?;
+    let after = crate::remaining_compute_units();
+
+    Ok(InvokeReceipt {
+        program_id: instruction.program_id,
+        accounts_len: instruction.accounts.len(),
+        data_len: instruction.data.len(),
+        compute_units: before.saturating_sub(after),
+    })
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use solana_program::instruction::AccountMeta;
+
+    use super::*;
+    use crate::mock;
+
+    #[test]
+    fn test_invoke_with_receipt() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            ],
+            data: vec![0; 600],
+        };
+        mock::set_mock_handler(|_, _, _| {
+            mock::consume_simulated_compute(42);
+            Ok(())
+        });
+
+        let unmetered = invoke_with_receipt(&instruction, &[], &[]);
+        mock::set_simulated_compute(10_000);
+        let metered = invoke_with_receipt(&instruction, &[], &[]);
+        mock::clear_simulated_compute();
+        mock::clear_mock_handler();
+
+        let expected = InvokeReceipt {
+            program_id: instruction.program_id,
+            accounts_len: 2,
+            data_len: 600,
+            compute_units: mock::INVOKE_UNITS + 600 / mock::CPI_BYTES_PER_UNIT + 42,
+        };
+        assert_eq!(metered, Ok(expected));
+        assert_eq!(
+            unmetered,
+            Ok(InvokeReceipt {
+                compute_units: 0,
+                ..expected
+            })
+        );
+    }
+}
@@ -0,0 +1,379 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+    program::MAX_RETURN_DATA, program_error::ProgramError, pubkey::Pubkey,
+};
+
+/// Same as [`invoke`](crate::invoke), but then reads the return data into
+/// `out`, returning the id of the program that set it.
+///
+/// `out` is cleared and only grows when its capacity is below
+/// [`MAX_RETURN_DATA`], so reusing the same buffer across many CPIs performs
+/// at most one allocation. The return data is copied straight into `out`
+/// instead of going through the stack buffer and fresh `Vec` of
+/// [`get_return_data`](crate::get_return_data).
+///
+/// Return data is not cleared after a CPI, so the returned program id may be
+/// that of a program further down the call stack; check it before trusting
+/// the data. If no return data is set, `out` is left empty and
+/// `Pubkey::default()` is returned.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_capturing(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    out: &mut Vec<u8>,
+) -> Result<Pubkey, ProgramError> {
+    crate::invoke(instruction, account_infos)?;
+
+    Ok(get_return_data_into(out).unwrap_or_default())
+}
+
+/// Same as [`invoke`](crate::invoke) for each instruction in order, reading
+/// the return data right after each CPI, before the next one overwrites it.
+///
+/// Returns one entry per instruction, with the id of the program that set the
+/// return data and the data, or `None` if there is none. Stops at the first
+/// failed CPI and returns its error, in which case the later instructions are
+/// not fired.
+///
+/// As with [`invoke_capturing`], return data is not cleared after a CPI, so a
+/// callee that doesn't set any may yield the return data of an earlier one;
+/// check the program ids.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[allow(clippy::type_complexity)] // one `get_return_data` result per instruction
+#[must_use = "the CPI may have failed"]
+pub fn invoke_many_capturing(
+    instructions: &[Instruction],
+    account_infos: &[AccountInfo],
+) -> Result<Vec<Option<(Pubkey, Vec<u8>)>>, ProgramError> {
+    let mut return_data = Vec::with_capacity(instructions.len());
+    for instruction in instructions {
+        crate::invoke(instruction, account_infos)?;
+        return_data.push(get_return_data());
+    }
+
+    Ok(return_data)
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), then sets the return data
+/// read after the CPI as this program's own, so that its caller sees it, as
+/// a program proxying the callee would.
+///
+/// If there is no return data, or it is empty, nothing is set. As with
+/// [`invoke_capturing`], the forwarded data may have been set by a program
+/// further down the call stack.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_and_forward_return_data(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    crate::invoke_signed(instruction, account_infos, signers_seeds)?;
+
+    if let Some((_, data)) = get_return_data() {
+        if !data.is_empty() {
+            solana_program::program::set_return_data(&data);
+        }
+    }
+
+    Ok(())
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), then passes a view of
+/// the return data read after the CPI (the id of the program that set it and
+/// the data), or `None` if there is none, to `f`, whose result is returned.
+///
+/// On-chain, the return data is read into a stack buffer that is dropped
+/// once `f` returns, so validating it transiently never allocates. As with
+/// [`invoke_capturing`], the data may have been set by a program further
+/// down the call stack.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_then<F: FnOnce(Option<(Pubkey, &[u8])>) -> ProgramResult>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+    f: F,
+) -> ProgramResult {
+    crate::invoke_signed(instruction, account_infos, signers_seeds)?;
+
+    #[cfg(target_os = "solana")]
+    {
+        let mut data = [0u8; MAX_RETURN_DATA];
+        let mut program_id = Pubkey::default();
+        let size = unsafe {
+            solana_program::syscalls::sol_get_return_data(
+                data.as_mut_ptr(),
+                MAX_RETURN_DATA as u64,
+                &mut program_id,
+            )
+        } as usize;
+        f((size != 0).then(|| (program_id, &data[..size.min(MAX_RETURN_DATA)])))
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    {
+        // Goes through the mock return data or the installed `SyscallStubs`
+        let return_data = get_return_data();
+        f(return_data
+            .as_ref()
+            .map(|(program_id, data)| (*program_id, &data[..data.len().min(MAX_RETURN_DATA)])))
+    }
+}
+
+/// Same as `solana_program::program::get_return_data`, with the same
+/// signature.
+///
+/// Off-chain with the `mock` feature, return data set with
+/// `mock::set_mock_return_data` on this thread takes precedence over the
+/// installed `SyscallStubs`.
+#[inline(always)]
+pub fn get_return_data() -> Option<(Pubkey, Vec<u8>)> {
+    #[cfg(all(feature = "mock", not(target_os = "solana")))]
+    if let Some(return_data) = crate::mock::mock_return_data() {
+        return Some(return_data);
+    }
+
+    solana_program::program::get_return_data()
+}
+
+/// Reads the current return data into `out` (cleared first), returning the
+/// id of the program that set it, or `None` if there is none.
+#[inline(always)]
+pub(crate) fn get_return_data_into(out: &mut Vec<u8>) -> Option<Pubkey> {
+    out.clear();
+
+    #[cfg(target_os = "solana")]
+    {
+        out.reserve(MAX_RETURN_DATA);
+        let mut program_id = Pubkey::default();
+        let size = unsafe {
+            solana_program::syscalls::sol_get_return_data(
+                out.as_mut_ptr(),
+                MAX_RETURN_DATA as u64,
+                &mut program_id,
+            )
+        } as usize;
+        if size == 0 {
+            return None;
+        }
+        // SAFETY: the runtime wrote `min(size, MAX_RETURN_DATA)` bytes, and
+        // the capacity is at least `MAX_RETURN_DATA`
+        unsafe { out.set_len(size.min(MAX_RETURN_DATA)) };
+        Some(program_id)
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    {
+        // Goes through the mock return data or the installed `SyscallStubs`
+        let (program_id, data) = get_return_data()?;
+        out.extend_from_slice(&data[..data.len().min(MAX_RETURN_DATA)]);
+        Some(program_id)
+    }
+}
+
+/// Reads the current return data into a stack array, returning `None` if
+/// there is none or if its length is not exactly `N`.
+///
+/// Unlike [`get_return_data`](crate::get_return_data), nothing is heap
+/// allocated, which suits fixed-size reads such as oracle prices.
+pub fn get_return_data_fixed<const N: usize>() -> Option<(Pubkey, [u8; N])> {
+    #[cfg(target_os = "solana")]
+    {
+        let mut data = [0u8; N];
+        let mut program_id = Pubkey::default();
+        let size = unsafe {
+            solana_program::syscalls::sol_get_return_data(
+                data.as_mut_ptr(),
+                N as u64,
+                &mut program_id,
+            )
+        } as usize;
+        (size != 0 && size == N).then_some((program_id, data))
+    }
+
+    #[cfg(not(target_os = "solana"))]
+    {
+        // Goes through the mock return data or the installed `SyscallStubs`
+        let (program_id, data) = get_return_data()?;
+        Some((program_id, data.try_into().ok()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "mock")]
+    use std::sync::Mutex;
+
+    use solana_program::program_stubs::{set_syscall_stubs, SyscallStubs};
+
+    use super::*;
+    use crate::STUBS_LOCK;
+
+    struct ReturnDataStubs(Pubkey);
+
+    impl SyscallStubs for ReturnDataStubs {
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            Some((self.0, vec![1, 2, 3, 4, 5, 6, 7, 8]))
+        }
+    }
+
+    #[test]
+    fn test_get_return_data_fixed() {
+        let program_id = Pubkey::new_unique();
+        let _lock = STUBS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous_stubs = set_syscall_stubs(Box::new(ReturnDataStubs(program_id)));
+        let matching = get_return_data_fixed::<8>();
+        let shorter = get_return_data_fixed::<4>();
+        let longer = get_return_data_fixed::<16>();
+        set_syscall_stubs(previous_stubs);
+
+        assert_eq!(matching, Some((program_id, [1, 2, 3, 4, 5, 6, 7, 8])));
+        assert_eq!(shorter, None);
+        assert_eq!(longer, None);
+    }
+
+    /// The return data set by the last mock callee.
+    #[cfg(feature = "mock")]
+    static RETURN_DATA: Mutex<Option<(Pubkey, Vec<u8>)>> = Mutex::new(None);
+
+    #[cfg(feature = "mock")]
+    struct MockReturnDataStubs;
+
+    /// The id the stubs record for return data set by the caller
+    #[cfg(feature = "mock")]
+    const CALLER: Pubkey = Pubkey::new_from_array([7; 32]);
+
+    #[cfg(feature = "mock")]
+    impl SyscallStubs for MockReturnDataStubs {
+        fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+            RETURN_DATA.lock().unwrap().clone()
+        }
+
+        fn sol_set_return_data(&self, data: &[u8]) {
+            *RETURN_DATA.lock().unwrap() = Some((CALLER, data.to_vec()));
+        }
+    }
+
+    #[cfg(all(feature = "mock", not(feature = "panic-on-cpi-error")))]
+    #[test]
+    fn test_invoke_many_capturing() {
+        let instructions =
+            [Pubkey::new_unique(), Pubkey::new_unique()].map(|program_id| Instruction {
+                program_id,
+                accounts: vec![],
+                data: program_id.to_bytes()[..4].to_vec(),
+            });
+
+        // Each callee returns its instruction data
+        crate::mock::set_mock_handler(|instruction, _, _| {
+            *RETURN_DATA.lock().unwrap() = Some((instruction.program_id, instruction.data.clone()));
+            Ok(())
+        });
+        let _lock = STUBS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous_stubs = set_syscall_stubs(Box::new(MockReturnDataStubs));
+        let return_data = invoke_many_capturing(&instructions, &[]);
+
+        // The first failure short-circuits
+        crate::mock::set_mock_handler(|instruction, _, _| {
+            if instruction.program_id.to_bytes()[..4] == instruction.data[..] {
+                return Err(ProgramError::Custom(1));
+            }
+            Ok(())
+        });
+        let failed = invoke_many_capturing(&instructions, &[]);
+        set_syscall_stubs(previous_stubs);
+        crate::mock::clear_mock_handler();
+
+        let expected: Vec<_> = instructions
+            .iter()
+            .map(|instruction| Some((instruction.program_id, instruction.data.clone())))
+            .collect();
+        assert_eq!(return_data, Ok(expected));
+        assert_eq!(failed, Err(ProgramError::Custom(1)));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_then() {
+        let program_id = Pubkey::new_unique();
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![],
+            data: vec![1, 2, 3],
+        };
+        /// Accepts the return data of `program_id` if it is 3 bytes long
+        fn validate(program_id: Pubkey) -> impl FnOnce(Option<(Pubkey, &[u8])>) -> ProgramResult {
+            move |return_data| match return_data {
+                Some((setter, data)) if setter == program_id && data.len() == 3 => Ok(()),
+                _ => Err(ProgramError::InvalidAccountData),
+            }
+        }
+
+        // The callee returns its instruction data, if any
+        crate::mock::set_mock_handler(|instruction, _, _| {
+            if instruction.data.is_empty() {
+                crate::mock::clear_mock_return_data();
+            } else {
+                crate::mock::set_mock_return_data(instruction.program_id, instruction.data.clone());
+            }
+            Ok(())
+        });
+        // Without mock return data, the default `SyscallStubs` report none
+        let _lock = STUBS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let valid = invoke_then(&instruction, &[], &[], validate(program_id));
+        let wrong_program = invoke_then(&instruction, &[], &[], validate(Pubkey::new_unique()));
+        let none = invoke_then(
+            &Instruction {
+                data: vec![],
+                ..instruction.clone()
+            },
+            &[],
+            &[],
+            |return_data| {
+                assert_eq!(return_data, None);
+                validate(program_id)(return_data)
+            },
+        );
+        crate::mock::clear_mock_handler();
+        crate::mock::clear_mock_return_data();
+
+        assert_eq!(valid, Ok(()));
+        assert_eq!(wrong_program, Err(ProgramError::InvalidAccountData));
+        assert_eq!(none, Err(ProgramError::InvalidAccountData));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_and_forward_return_data() {
+        let mut instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![],
+            data: vec![1, 2, 3],
+        };
+
+        // The callee returns its instruction data, if any
+        crate::mock::set_mock_handler(|instruction, _, _| {
+            *RETURN_DATA.lock().unwrap() = (!instruction.data.is_empty())
+                .then(|| (instruction.program_id, instruction.data.clone()));
+            Ok(())
+        });
+        let _lock = STUBS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous_stubs = set_syscall_stubs(Box::new(MockReturnDataStubs));
+        let forwarded = invoke_and_forward_return_data(&instruction, &[], &[]);
+        let forwarded_data = RETURN_DATA.lock().unwrap().take();
+
+        // Nothing to forward
+        instruction.data.clear();
+        let empty = invoke_and_forward_return_data(&instruction, &[], &[]);
+        let empty_data = RETURN_DATA.lock().unwrap().take();
+        set_syscall_stubs(previous_stubs);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(forwarded, Ok(()));
+        assert_eq!(forwarded_data, Some((CALLER, vec![1, 2, 3])));
+        assert_eq!(empty, Ok(()));
+        assert_eq!(empty_data, None);
+    }
+}
@@ -0,0 +1,336 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    program_error::ProgramError,
+    pubkey::{MAX_SEEDS, MAX_SEED_LEN},
+};
+
+use crate::MAX_SIGNERS;
+
+/// Every byte, so that a bump seed can be borrowed for any lifetime instead
+/// of from the builder holding it.
+static BUMPS: [u8; 256] = {
+    let mut bumps = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        bumps[i] = i as u8;
+        i += 1;
+    }
+    bumps
+};
+
+/// A single group of signer seeds, assembled on the stack for
+/// [`invoke_signed_with`].
+///
+/// Seeds beyond `MAX_SEEDS` or longer than `MAX_SEED_LEN` are not added, and
+/// make [`SeedBuilder::build`] (and so [`invoke_signed_with`]) return
+/// [`ProgramError::MaxSeedLengthExceeded`].
+pub struct SeedBuilder<'a> {
+    seeds: [&'a [u8]; MAX_SEEDS],
+    len: usize,
+    exceeded: bool,
+}
+
+impl<'a> SeedBuilder<'a> {
+    /// An empty seed group.
+    pub const fn new() -> SeedBuilder<'a> {
+        SeedBuilder {
+            seeds: [&[]; MAX_SEEDS],
+            len: 0,
+            exceeded: false,
+        }
+    }
+
+    /// Appends a seed.
+    #[inline(always)]
+    pub fn seed(mut self, seed: &'a [u8]) -> SeedBuilder<'a> {
+        if self.len == MAX_SEEDS || seed.len() > MAX_SEED_LEN {
+            self.exceeded = true;
+        } else {
+            self.seeds[self.len] = seed;
+            self.len += 1;
+        }
+        self
+    }
+
+    /// Appends a bump seed.
+    #[inline(always)]
+    pub fn bump(self, bump: u8) -> SeedBuilder<'a> {
+        let bump = &BUMPS[bump as usize..][..1];
+        self.seed(bump)
+    }
+
+    /// The seeds appended so far, or [`ProgramError::MaxSeedLengthExceeded`]
+    /// if any exceeded the limits.
+    #[inline(always)]
+    pub fn build(&self) -> Result<&[&'a [u8]], ProgramError> {
+        if self.exceeded {
+            return Err(ProgramError::MaxSeedLengthExceeded);
+        }
+
+        Ok(&self.seeds[..self.len])
+    }
+}
+
+impl Default for SeedBuilder<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The seed groups of several PDA signers, gathered on the stack.
+///
+/// Groups are borrowed, not copied, so slices they have in common, such as a
+/// shared prefix, are referenced by each group rather than duplicated:
+///
+/// ```no_run
+/// use solana_program::{
+///     account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction,
+/// };
+/// use solana_invoke::SignerSet;
+///
+/// fn settle(
+///     instruction: &Instruction,
+///     accounts: &[AccountInfo],
+///     market: &[u8],
+///     (bump_a, bump_b): (u8, u8),
+/// ) -> ProgramResult {
+///     SignerSet::new()
+///         .add(&[market, b"vault_a", &[bump_a]])
+///         .add(&[market, b"vault_b", &[bump_b]])
+///         .invoke(instruction, accounts)
+/// }
+/// ```
+///
+/// Groups written inline like this are temporaries, so the set is built and
+/// fired in a single expression. Groups beyond [`MAX_SIGNERS`] are not added,
+/// and make [`SignerSet::signers_seeds`] (and so [`SignerSet::invoke`])
+/// return [`ProgramError::InvalidArgument`], as the runtime would reject
+/// them.
+pub struct SignerSet<'a> {
+    groups: [&'a [&'a [u8]]; MAX_SIGNERS],
+    len: usize,
+    exceeded: bool,
+}
+
+impl<'a> SignerSet<'a> {
+    /// An empty set of signers.
+    pub const fn new() -> SignerSet<'a> {
+        SignerSet {
+            groups: [&[]; MAX_SIGNERS],
+            len: 0,
+            exceeded: false,
+        }
+    }
+
+    /// Adds the seeds of a PDA signer, bump included.
+    #[inline(always)]
+    #[allow(clippy::should_implement_trait)] // a builder method, not `+`
+    pub fn add(mut self, seeds: &'a [&'a [u8]]) -> SignerSet<'a> {
+        if self.len == MAX_SIGNERS {
+            self.exceeded = true;
+        } else {
+            self.groups[self.len] = seeds;
+            self.len += 1;
+        }
+        self
+    }
+
+    /// The seed groups added so far, or [`ProgramError::InvalidArgument`]
+    /// if there were more than [`MAX_SIGNERS`].
+    #[inline(always)]
+    pub fn signers_seeds(&self) -> Result<&[&'a [&'a [u8]]], ProgramError> {
+        if self.exceeded {
+            return Err(ProgramError::InvalidArgument);
+        }
+
+        Ok(&self.groups[..self.len])
+    }
+
+    /// Same as [`invoke_signed`](crate::invoke_signed), signed for by the
+    /// seed groups of the set.
+    #[cfg_attr(not(target_os = "solana"), track_caller)]
+    #[must_use = "the CPI may have failed"]
+    pub fn invoke(
+        &self,
+        instruction: &Instruction,
+        account_infos: &[AccountInfo],
+    ) -> ProgramResult {
+        crate::invoke_signed(instruction, account_infos, self.signers_seeds()?)
+    }
+}
+
+impl Default for SignerSet<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Same as [`invoke_signed_single`](crate::invoke_signed_single), with the
+/// seeds of the PDA signer appended by `seeds` to a [`SeedBuilder`], so that
+/// no nested slices have to be written out by hand:
+///
+/// ```no_run
+/// use solana_program::{
+///     account_info::AccountInfo, entrypoint::ProgramResult, system_instruction,
+/// };
+///
+/// fn withdraw(accounts: &[AccountInfo], bump: u8) -> ProgramResult {
+///     let (vault, owner, recipient) = (&accounts[0], &accounts[1], &accounts[2]);
+///
+///     solana_invoke::invoke_signed_with(
+///         &system_instruction::transfer(vault.key, recipient.key, 1),
+///         accounts,
+///         |builder| builder.seed(b"vault").seed(owner.key.as_ref()).bump(bump),
+///     )
+/// }
+/// ```
+///
+/// Everything stays on the stack. Returns
+/// [`ProgramError::MaxSeedLengthExceeded`] without firing the CPI if the
+/// seeds exceed the runtime's limits.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_with<'a, F>(
+    instruction: &Instruction,
+    account_infos: &[AccountInfo],
+    seeds: F,
+) -> ProgramResult
+where
+    F: FnOnce(SeedBuilder<'a>) -> SeedBuilder<'a>,
+{
+    let builder = seeds(SeedBuilder::new());
+    let seeds = builder.build()?;
+
+    crate::invoke_signed_single(instruction, account_infos, seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::pubkey::Pubkey;
+
+    use super::*;
+
+    #[test]
+    fn test_seed_builder() {
+        let key = Pubkey::new_unique();
+        let builder = SeedBuilder::new().seed(b"vault").seed(key.as_ref()).bump(7);
+        assert_eq!(
+            builder.build(),
+            Ok(&[&b"vault"[..], key.as_ref(), &[7]][..])
+        );
+        for bump in [0, 255] {
+            assert_eq!(
+                SeedBuilder::new().bump(bump).build(),
+                Ok(&[&[bump][..]][..])
+            );
+        }
+
+        // Too many seeds, or too long a seed
+        let too_many = (0..=MAX_SEEDS).fold(SeedBuilder::new(), |builder, _| builder.seed(b"a"));
+        assert_eq!(too_many.build(), Err(ProgramError::MaxSeedLengthExceeded));
+        let too_long = SeedBuilder::new().seed(&[0; MAX_SEED_LEN + 1]);
+        assert_eq!(too_long.build(), Err(ProgramError::MaxSeedLengthExceeded));
+        let longest = SeedBuilder::new().seed(&[0; MAX_SEED_LEN]);
+        assert_eq!(longest.build().map(<[_]>::len), Ok(1));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke_signed_with() {
+        const PROGRAM_ID: Pubkey = Pubkey::new_from_array([5; 32]);
+        let owner = Pubkey::new_unique();
+        let (vault, bump) =
+            Pubkey::find_program_address(&[b"vault", owner.as_ref(), &[1, 2, 3]], &PROGRAM_ID);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![solana_program::instruction::AccountMeta::new_readonly(
+                vault, true,
+            )],
+            data: vec![],
+        };
+
+        // The callee checks that the vault signed
+        crate::mock::set_mock_handler(|instruction, _, signers_seeds| {
+            let [seeds] = signers_seeds else {
+                return Err(ProgramError::MissingRequiredSignature);
+            };
+            match Pubkey::create_program_address(seeds, &PROGRAM_ID) {
+                Ok(signer) if signer == instruction.accounts[0].pubkey => Ok(()),
+                _ => Err(ProgramError::MissingRequiredSignature),
+            }
+        });
+        let signed = invoke_signed_with(&instruction, &[], |builder| {
+            builder
+                .seed(b"vault")
+                .seed(owner.as_ref())
+                .seed(&[1, 2, 3])
+                .bump(bump)
+        });
+        let exceeded = invoke_signed_with(&instruction, &[], |builder| {
+            builder.seed(&[0; MAX_SEED_LEN + 1]).bump(bump)
+        });
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(signed, Ok(()));
+        assert_eq!(exceeded, Err(ProgramError::MaxSeedLengthExceeded));
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_signer_set_shared_prefix() {
+        const PROGRAM_ID: Pubkey = Pubkey::new_from_array([6; 32]);
+        let market = Pubkey::new_unique();
+        let prefix = market.as_ref();
+        let (vault_a, bump_a) = Pubkey::find_program_address(&[prefix, b"a"], &PROGRAM_ID);
+        let (vault_b, bump_b) = Pubkey::find_program_address(&[prefix, b"b"], &PROGRAM_ID);
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                solana_program::instruction::AccountMeta::new_readonly(vault_a, true),
+                solana_program::instruction::AccountMeta::new_readonly(vault_b, true),
+            ],
+            data: vec![],
+        };
+
+        // The callee checks that both vaults signed
+        crate::mock::set_mock_handler(|instruction, _, signers_seeds| {
+            let signers: Vec<Pubkey> = signers_seeds
+                .iter()
+                .map(|seeds| Pubkey::create_program_address(seeds, &PROGRAM_ID).unwrap())
+                .collect();
+            for account_meta in instruction.accounts.iter() {
+                if !signers.contains(&account_meta.pubkey) {
+                    return Err(ProgramError::MissingRequiredSignature);
+                }
+            }
+            Ok(())
+        });
+        let signed = SignerSet::new()
+            .add(&[prefix, b"a", &[bump_a]])
+            .add(&[prefix, b"b", &[bump_b]])
+            .invoke(&instruction, &[]);
+        crate::mock::clear_mock_handler();
+        assert_eq!(signed, Ok(()));
+
+        let (seeds_a, seeds_b): (&[&[u8]], &[&[u8]]) =
+            (&[prefix, b"a", &[bump_a]], &[prefix, b"b", &[bump_b]]);
+        let set = SignerSet::new().add(seeds_a).add(seeds_b);
+        let groups = set.signers_seeds().unwrap();
+        // The prefix is shared by reference, not copied
+        assert!(core::ptr::eq(groups[0][0], groups[1][0]));
+        assert!(core::ptr::eq(groups[0][0], prefix));
+    }
+
+    #[test]
+    fn test_signer_set_max_signers() {
+        let seeds: &[&[u8]] = &[b"vault"];
+        let full = (0..MAX_SIGNERS).fold(SignerSet::new(), |set, _| set.add(seeds));
+        assert_eq!(full.signers_seeds().map(<[_]>::len), Ok(MAX_SIGNERS));
+        assert_eq!(
+            full.add(seeds).signers_seeds(),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! A compact byte encoding of instructions, for audit logging and for
+//! forwarding instruction descriptions to observers (e.g. via return data or
+//! `sol_log_data`).
+//!
+//! The layout is, with all integers little-endian:
+//!
+//! ```text
+//! program_id      [u8; 32]
+//! accounts_len    u32
+//! accounts        accounts_len * { pubkey: [u8; 32], flags: u8 }
+//! data_len        u32
+//! data            [u8; data_len]
+//! ```
+//!
+//! where bit 0 of `flags` is `is_signer` and bit 1 is `is_writable`. All
+//! other flag bits are zero.
+//!
+//! Encoding goes through the same stabilized view the syscall is handed, so
+//! off-chain tooling (e.g. an indexer replaying instructions) gets the exact
+//! on-chain layout without ever reaching the syscall. Like the stabilizer,
+//! this module only uses `core` and `alloc`; the crate as a whole still
+//! requires `std`, as `solana-program` does.
+
+use solana_program::{
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    stable_layout::stable_instruction::StableInstruction,
+};
+
+use alloc::vec::Vec;
+
+use crate::instruction_stabilizer::InstructionStabilizer;
+
+const SIGNER_FLAG: u8 = 1 << 0;
+const WRITABLE_FLAG: u8 = 1 << 1;
+
+/// Number of bytes [`serialize_stable`] appends for an instruction with the
+/// given number of accounts and data length.
+#[inline(always)]
+pub const fn serialized_len(accounts_len: usize, data_len: usize) -> usize {
+    32 + 4 + accounts_len * 33 + 4 + data_len
+}
+
+/// Appends the encoding of a stabilized instruction to `out`. The
+/// instruction is only read.
+pub fn serialize_stable(stable: &StableInstruction, out: &mut Vec<u8>) {
+    let (accounts, data): (&[AccountMeta], &[u8]) = (&stable.accounts, &stable.data);
+    out.reserve(serialized_len(accounts.len(), data.len()));
+    out.extend_from_slice(stable.program_id.as_ref());
+    out.extend_from_slice(&(accounts.len() as u32).to_le_bytes());
+    for account_meta in accounts.iter() {
+        out.extend_from_slice(account_meta.pubkey.as_ref());
+        let mut flags = 0;
+        if account_meta.is_signer {
+            flags |= SIGNER_FLAG;
+        }
+        if account_meta.is_writable {
+            flags |= WRITABLE_FLAG;
+        }
+        out.push(flags);
+    }
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+/// Appends the encoding of an instruction to `out`, stabilizing it without
+/// copying first.
+pub fn serialize_instruction(instruction: &Instruction, out: &mut Vec<u8>) {
+    let stabilizer = InstructionStabilizer::stabilize(instruction);
+    serialize_stable(stabilizer.stable_instruction_ref(), out)
+}
+
+/// Decodes an instruction encoded by [`serialize_stable`].
+///
+/// Returns [`ProgramError::InvalidInstructionData`] if the bytes are
+/// truncated, have trailing bytes, or contain unknown flag bits.
+pub fn deserialize_instruction(bytes: &[u8]) -> Result<Instruction, ProgramError> {
+    let mut reader = Reader { bytes };
+
+    let program_id = Pubkey::new_from_array(reader.array()?);
+    let accounts_len = u32::from_le_bytes(reader.array()?) as usize;
+    // Don't trust the length for preallocation beyond what the input can hold
+    let mut accounts = Vec::with_capacity(accounts_len.min(reader.bytes.len() / 33));
+    for _ in 0..accounts_len {
+        let pubkey = Pubkey::new_from_array(reader.array()?);
+        let [flags] = reader.array()?;
+        if flags & !(SIGNER_FLAG | WRITABLE_FLAG) != 0 {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        accounts.push(AccountMeta {
+            pubkey,
+            is_signer: flags & SIGNER_FLAG != 0,
+            is_writable: flags & WRITABLE_FLAG != 0,
+        });
+    }
+    let data_len = u32::from_le_bytes(reader.array()?) as usize;
+    let data = reader.take(data_len)?.to_vec();
+    if !reader.bytes.is_empty() {
+        return Err(ProgramError::InvalidInstructionData);
+    }
+
+    Ok(Instruction {
+        program_id,
+        accounts,
+        data,
+    })
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ProgramError> {
+        if self.bytes.len() < len {
+            return Err(ProgramError::InvalidInstructionData);
+        }
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], ProgramError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let instruction = Instruction {
+            program_id: Pubkey::new_unique(),
+            accounts: vec![
+                AccountMeta::new(Pubkey::new_unique(), true),
+                AccountMeta::new(Pubkey::new_unique(), false),
+                AccountMeta::new_readonly(Pubkey::new_unique(), true),
+                AccountMeta::new_readonly(Pubkey::new_unique(), false),
+            ],
+            data: vec![1, 2, 3, 4, 5],
+        };
+
+        let mut out = vec![0xff];
+        serialize_instruction(&instruction, &mut out);
+        assert_eq!(out.len(), 1 + serialized_len(4, 5));
+        assert_eq!(out[1 + 32 + 4 + 32], SIGNER_FLAG | WRITABLE_FLAG);
+        assert_eq!(deserialize_instruction(&out[1..]), Ok(instruction));
+
+        // Lengths are little-endian regardless of the host's byte order
+        assert_eq!(out[1 + 32..1 + 32 + 4], [4, 0, 0, 0]);
+        assert_eq!(out[out.len() - 5 - 4..out.len() - 5], [5, 0, 0, 0]);
+
+        // Truncated and trailing input are rejected
+        assert_eq!(
+            deserialize_instruction(&out[1..out.len() - 1]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+        out.push(0);
+        assert_eq!(
+            deserialize_instruction(&out[1..]),
+            Err(ProgramError::InvalidInstructionData)
+        );
+    }
+}
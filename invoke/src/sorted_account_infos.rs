@@ -0,0 +1,185 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::Instruction, pubkey::Pubkey,
+};
+
+use crate::checks;
+
+/// A view over a slice of account infos, sorted once by key so the borrow
+/// check of every subsequent invoke uses binary search instead of a linear
+/// scan.
+///
+/// This pays off for handlers that fire several CPIs over the same large
+/// (30+) account set. The checks are otherwise those of
+/// [`invoke_signed`](crate::invoke_signed). Only references are sorted; the original slice is
+/// borrowed, never copied, and is what gets passed to the runtime.
+pub struct SortedAccountInfos<'a, 'info> {
+    account_infos: &'a [AccountInfo<'info>],
+    sorted: Vec<&'a AccountInfo<'info>>,
+}
+
+impl<'a, 'info> SortedAccountInfos<'a, 'info> {
+    pub fn new(account_infos: &'a [AccountInfo<'info>]) -> SortedAccountInfos<'a, 'info> {
+        let mut sorted: Vec<&AccountInfo> = account_infos.iter().collect();
+        // Stable, so duplicates keep their original order and lookups
+        // resolve to the same account info as a linear scan would
+        sorted.sort_by(|a, b| a.key.cmp(b.key));
+        SortedAccountInfos {
+            account_infos,
+            sorted,
+        }
+    }
+
+    /// The original, unsorted account infos.
+    #[inline(always)]
+    pub fn account_infos(&self) -> &'a [AccountInfo<'info>] {
+        self.account_infos
+    }
+
+    /// Returns the first account info (in the original order) with this key.
+    #[inline(always)]
+    pub fn find(&self, key: &Pubkey) -> Option<&'a AccountInfo<'info>> {
+        let index = self
+            .sorted
+            .partition_point(|account_info| account_info.key < key);
+        self.sorted
+            .get(index)
+            .copied()
+            .filter(|account_info| account_info.key == key)
+    }
+
+    /// Same as [`invoke`](crate::invoke) over the original account infos.
+    #[cfg_attr(not(target_os = "solana"), track_caller)]
+    #[must_use = "the CPI may have failed"]
+    pub fn invoke(&self, instruction: &Instruction) -> ProgramResult {
+        self.invoke_signed(instruction, &[])
+    }
+
+    /// Same as [`invoke_signed`](crate::invoke_signed) over the original
+    /// account infos.
+    #[cfg_attr(not(target_os = "solana"), track_caller)]
+    #[must_use = "the CPI may have failed"]
+    pub fn invoke_signed(
+        &self,
+        instruction: &Instruction,
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        checks::check_accounts_len(&instruction.accounts)?;
+        checks::check_borrows_by(&instruction.accounts, |account_meta| {
+            self.find(&account_meta.pubkey)
+        })?;
+
+        crate::invoke_signed_unchecked(instruction, self.account_infos, signers_seeds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find() {
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..32).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [0u64; 33];
+        let mut data = [[0u8; 0]; 33];
+        // The last account info duplicates the first key
+        let account_infos: Vec<AccountInfo> = keys
+            .iter()
+            .chain(keys.first())
+            .zip(lamports.iter_mut())
+            .zip(data.iter_mut())
+            .enumerate()
+            .map(|(i, ((key, lamports), data))| {
+                AccountInfo::new(key, false, i < 32, lamports, data, &owner, false, 0)
+            })
+            .collect();
+
+        let sorted = SortedAccountInfos::new(&account_infos);
+        for (account_info, key) in account_infos.iter().zip(keys.iter()) {
+            let found = sorted.find(key).unwrap();
+            assert!(core::ptr::eq(found, account_info));
+        }
+        // Duplicates resolve to the first occurrence
+        assert!(sorted.find(&keys[0]).unwrap().is_writable);
+        assert!(sorted.find(&Pubkey::new_unique()).is_none());
+    }
+
+    #[cfg(feature = "mock")]
+    #[test]
+    fn test_invoke() {
+        use solana_program::{instruction::AccountMeta, program_error::ProgramError};
+
+        let owner = Pubkey::new_unique();
+        let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+        let mut lamports = [0u64; 4];
+        let mut data = [[0u8; 1]; 4];
+        let account_infos =
+            crate::test_utils::writable_account_infos(&keys, &mut lamports, &mut data, &owner);
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[],
+            vec![
+                AccountMeta::new(keys[3], false),
+                AccountMeta::new_readonly(keys[1], false),
+            ],
+        );
+
+        // Writes to the account of the first account meta
+        crate::mock::set_mock_handler(|instruction, account_infos, _| {
+            let key = instruction.accounts[0].pubkey;
+            let account_info = account_infos.iter().find(|a| *a.key == key).unwrap();
+            account_info.try_borrow_mut_data()?[0] += 1;
+            Ok(())
+        });
+        let sorted = SortedAccountInfos::new(&account_infos);
+        let invoked = sorted.invoke(&instruction);
+        let signed = sorted.invoke_signed(&instruction, &[&[b"seed"]]);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(invoked, Ok(()));
+        assert_eq!(signed, Ok(()));
+        assert_eq!(account_infos[3].try_borrow_data().unwrap()[0], 2);
+
+        // Checked like `invoke_signed`
+        if !checks::NO_BORROW_CHECK {
+            let guard = account_infos[3].try_borrow_data().unwrap();
+            assert_eq!(
+                sorted.invoke(&instruction),
+                Err(ProgramError::AccountBorrowFailed)
+            );
+            assert_eq!(
+                crate::invoke(&instruction, &account_infos),
+                Err(ProgramError::AccountBorrowFailed)
+            );
+            drop(guard);
+
+            let _guard = account_infos[1].try_borrow_mut_lamports().unwrap();
+            assert_eq!(
+                sorted.invoke_signed(&instruction, &[]),
+                Err(ProgramError::AccountBorrowFailed)
+            );
+        }
+        let too_many = Instruction::new_with_bytes(
+            instruction.program_id,
+            &[],
+            vec![
+                AccountMeta::new_readonly(keys[0], false);
+                crate::MAX_CPI_INSTRUCTION_ACCOUNTS + 1
+            ],
+        );
+        assert_eq!(sorted.invoke(&too_many), Err(ProgramError::InvalidArgument));
+
+        // And with the `strict` feature, a missing writable account
+        if cfg!(feature = "strict") {
+            let missing = Instruction::new_with_bytes(
+                instruction.program_id,
+                &[],
+                vec![AccountMeta::new(Pubkey::new_unique(), false)],
+            );
+            assert_eq!(
+                sorted.invoke(&missing),
+                Err(ProgramError::NotEnoughAccountKeys)
+            );
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta, pubkey::Pubkey,
+};
+
+use crate::{checks, instruction_stabilizer::StableVec};
+
+/// An instruction assembled entirely at compile time.
+///
+/// This has the same layout as `StableInstruction`, so it can be handed to
+/// the syscall directly with zero runtime assembly: no `Instruction`, no
+/// `Vec`s, and no stabilization. It only ever views `'static` data and has no
+/// `Drop` implementation, so it is safe to copy around and to store in a
+/// `const` or `static`.
+///
+/// ```
+/// use solana_invoke::StaticInstruction;
+/// use solana_program::{instruction::AccountMeta, pubkey, pubkey::Pubkey};
+///
+/// const PROGRAM: Pubkey = pubkey!("Memo1UhkJRfHyvLMcVucJwxXeuD728EqVDDwQDxFMNo");
+/// const SIGNER: Pubkey = pubkey!("SysvarC1ock11111111111111111111111111111111");
+/// const METAS: &[AccountMeta] = &[AccountMeta {
+///     pubkey: SIGNER,
+///     is_signer: false,
+///     is_writable: false,
+/// }];
+/// static MEMO: StaticInstruction = StaticInstruction::new(PROGRAM, METAS, b"gm");
+///
+/// assert_eq!(MEMO.accounts(), METAS);
+/// assert_eq!(MEMO.data(), b"gm");
+/// ```
+#[repr(C)]
+pub struct StaticInstruction {
+    accounts: StableVec<AccountMeta>,
+    data: StableVec<u8>,
+    program_id: Pubkey,
+}
+
+// SAFETY: only ever points to immutable `'static` data
+unsafe impl Sync for StaticInstruction {}
+
+const _: () = assert!(
+    core::mem::size_of::<StaticInstruction>()
+        == core::mem::size_of::<solana_program::stable_layout::stable_instruction::StableInstruction>(
+        )
+);
+
+impl StaticInstruction {
+    pub const fn new(
+        program_id: Pubkey,
+        accounts: &'static [AccountMeta],
+        data: &'static [u8],
+    ) -> StaticInstruction {
+        StaticInstruction {
+            accounts: StableVec::from_static(accounts),
+            data: StableVec::from_static(data),
+            program_id,
+        }
+    }
+
+    #[inline(always)]
+    pub fn program_id(&self) -> &Pubkey {
+        &self.program_id
+    }
+
+    #[inline(always)]
+    pub fn accounts(&self) -> &'static [AccountMeta] {
+        // SAFETY: the fields are private and only set by `new`, from the
+        // pointer and length of a `&'static [AccountMeta]`, which stays
+        // valid and immutable for `'static`
+        unsafe {
+            core::slice::from_raw_parts(self.accounts.ptr.as_ptr(), self.accounts.len as usize)
+        }
+    }
+
+    #[inline(always)]
+    pub fn data(&self) -> &'static [u8] {
+        // SAFETY: as in `accounts`, from the `&'static [u8]` given to `new`
+        unsafe { core::slice::from_raw_parts(self.data.ptr.as_ptr(), self.data.len as usize) }
+    }
+
+    #[inline(always)]
+    pub fn instruction_addr(&self) -> *const u8 {
+        self as *const StaticInstruction as *const u8
+    }
+}
+
+/// Same as [`invoke`](crate::invoke), for an instruction built at compile time.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_static(
+    instruction: &StaticInstruction,
+    account_infos: &[AccountInfo],
+) -> ProgramResult {
+    invoke_signed_static(instruction, account_infos, &[])
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), for an instruction built
+/// at compile time.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_static(
+    instruction: &StaticInstruction,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_accounts_len(instruction.accounts())?;
+    checks::check_borrows(instruction.accounts(), account_infos)?;
+
+    crate::invoke_signed_raw(instruction.instruction_addr(), account_infos, signers_seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_program::{
+        instruction::Instruction, stable_layout::stable_instruction::StableInstruction,
+    };
+
+    use super::*;
+
+    const PROGRAM_ID: Pubkey = Pubkey::new_from_array([7; 32]);
+    const METAS: &[AccountMeta] = &[
+        AccountMeta {
+            pubkey: Pubkey::new_from_array([1; 32]),
+            is_signer: true,
+            is_writable: false,
+        },
+        AccountMeta {
+            pubkey: Pubkey::new_from_array([2; 32]),
+            is_signer: false,
+            is_writable: true,
+        },
+    ];
+    const STATIC: StaticInstruction = StaticInstruction::new(PROGRAM_ID, METAS, &[1, 2, 3]);
+
+    #[test]
+    fn test_static_instruction_layout() {
+        // Reading it back as the runtime would must yield the same instruction
+        let stable = unsafe { &*(STATIC.instruction_addr() as *const StableInstruction) };
+        let expected = Instruction {
+            program_id: PROGRAM_ID,
+            accounts: METAS.to_vec(),
+            data: vec![1, 2, 3],
+        };
+        assert_eq!(stable.program_id, expected.program_id);
+        assert_eq!(&stable.accounts[..], &expected.accounts[..]);
+        assert_eq!(&stable.data[..], &expected.data[..]);
+
+        // Empty static data
+        const EMPTY: StaticInstruction = StaticInstruction::new(PROGRAM_ID, &[], &[]);
+        let stable = unsafe { &*(EMPTY.instruction_addr() as *const StableInstruction) };
+        assert!(stable.accounts.is_empty());
+        assert!(stable.data.is_empty());
+    }
+
+    #[test]
+    fn test_invoke_too_many_accounts() {
+        let metas = vec![METAS[0].clone(); crate::MAX_CPI_INSTRUCTION_ACCOUNTS + 1].leak();
+        let instruction = StaticInstruction::new(PROGRAM_ID, metas, &[]);
+        assert_eq!(
+            invoke_static(&instruction, &[]),
+            Err(solana_program::program_error::ProgramError::InvalidArgument)
+        );
+    }
+}
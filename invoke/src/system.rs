@@ -0,0 +1,262 @@
+//! Helpers for CPIs to the system program, fired through
+//! [`invoke_to`](crate::invoke_to) with the instructions encoded on the stack.
+
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta, pubkey::Pubkey,
+};
+
+use crate::{checks, invoke_to, SystemProgram};
+
+/// `SystemInstruction::CreateAccount` tag
+const CREATE_ACCOUNT: u32 = 0;
+/// `SystemInstruction::Assign` tag
+const ASSIGN: u32 = 1;
+/// `SystemInstruction::Allocate` tag
+const ALLOCATE: u32 = 8;
+
+/// Allocates `space` bytes of data for `account` and then assigns it to
+/// `owner`, e.g. to initialize a PDA that already holds enough lamports to be
+/// rent-exempt.
+///
+/// The system program only allocates accounts it owns, so the order matters:
+/// `allocate` is fired first, then `assign`. No borrow is held across either
+/// CPI, and `account`'s data length reflects `space` by the time `assign` is
+/// fired. `account` must be a signer, or a PDA signed for by
+/// `signers_seeds`.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn allocate_and_assign(
+    account: &AccountInfo,
+    space: u64,
+    owner: &Pubkey,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let account_metas = [AccountMeta::new(*account.key, true)];
+    let account_infos = core::slice::from_ref(account);
+
+    let mut allocate = [0u8; 12];
+    allocate[..4].copy_from_slice(&ALLOCATE.to_le_bytes());
+    allocate[4..].copy_from_slice(&space.to_le_bytes());
+    invoke_to::<SystemProgram>(&account_metas, &allocate, account_infos, signers_seeds)?;
+
+    let mut assign = [0u8; 36];
+    assign[..4].copy_from_slice(&ASSIGN.to_le_bytes());
+    assign[4..].copy_from_slice(owner.as_ref());
+    invoke_to::<SystemProgram>(&account_metas, &assign, account_infos, signers_seeds)
+}
+
+/// Creates `new_account` with `lamports` funded by `payer`, `space` bytes of
+/// data and `owner` as its owner, as the system program's `create_account`.
+///
+/// Both `payer` and `new_account` must be signers, or PDAs signed for by
+/// `signers_seeds`. See [`create_account_rent_exempt`] to also check that
+/// `lamports` cover rent exemption.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn create_account<'info>(
+    payer: &AccountInfo<'info>,
+    new_account: &AccountInfo<'info>,
+    lamports: u64,
+    space: u64,
+    owner: &Pubkey,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let account_metas = [
+        AccountMeta::new(*payer.key, true),
+        AccountMeta::new(*new_account.key, true),
+    ];
+
+    let mut data = [0u8; 52];
+    data[..4].copy_from_slice(&CREATE_ACCOUNT.to_le_bytes());
+    data[4..12].copy_from_slice(&lamports.to_le_bytes());
+    data[12..20].copy_from_slice(&space.to_le_bytes());
+    data[20..].copy_from_slice(owner.as_ref());
+    invoke_to::<SystemProgram>(
+        &account_metas,
+        &data,
+        &[payer.clone(), new_account.clone()],
+        signers_seeds,
+    )
+}
+
+/// Same as [`create_account`], but first checks with the `Rent` sysvar that
+/// `lamports` make an account of `space` bytes rent-exempt, returning
+/// [`ProgramError::AccountNotRentExempt`] otherwise.
+///
+/// The system program happily creates an account that isn't rent-exempt, and
+/// the mistake only shows later, when a transaction leaves it with a balance
+/// the runtime rejects. This catches it at the call site instead, for one
+/// sysvar read.
+///
+/// [`ProgramError::AccountNotRentExempt`]: solana_program::program_error::ProgramError::AccountNotRentExempt
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn create_account_rent_exempt<'info>(
+    payer: &AccountInfo<'info>,
+    new_account: &AccountInfo<'info>,
+    lamports: u64,
+    space: u64,
+    owner: &Pubkey,
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    checks::check_rent_exempt(lamports, space)?;
+
+    create_account(payer, new_account, lamports, space, owner, signers_seeds)
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use solana_program::program_error::ProgramError;
+
+    use super::*;
+
+    const PROGRAM_ID: Pubkey = Pubkey::new_from_array([3; 32]);
+
+    /// Emulates the system program's `allocate` and `assign` for a PDA of
+    /// `PROGRAM_ID`
+    fn system_program(
+        instruction: &solana_program::instruction::Instruction,
+        account_infos: &[AccountInfo],
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        assert_eq!(instruction.program_id, crate::SYSTEM_PROGRAM_ID);
+        let account = &account_infos[0];
+        let signed = signers_seeds.iter().any(|seeds| {
+            Pubkey::create_program_address(seeds, &PROGRAM_ID).as_ref() == Ok(account.key)
+        });
+        if !signed {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        if *account.owner != crate::SYSTEM_PROGRAM_ID {
+            return Err(ProgramError::IllegalOwner);
+        }
+
+        if let Ok(space) = <[u8; 8]>::try_from(&instruction.data[4..]) {
+            let space = u64::from_le_bytes(space) as usize;
+            assert_eq!(
+                *instruction,
+                solana_program::system_instruction::allocate(account.key, space as u64)
+            );
+            // Leaked to outlive the account info, as account data does
+            *account.try_borrow_mut_data()? = Box::leak(vec![0; space].into_boxed_slice());
+        } else {
+            let owner = Pubkey::try_from(&instruction.data[4..]).unwrap();
+            assert_eq!(
+                *instruction,
+                solana_program::system_instruction::assign(account.key, &owner)
+            );
+            account.assign(&owner);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_and_assign_pda() {
+        let owner = Pubkey::new_unique();
+        let (pda, bump) = Pubkey::find_program_address(&[b"state"], &PROGRAM_ID);
+        let mut lamports = 1_000_000;
+        let mut data = [0u8; 0];
+        let current_owner = crate::SYSTEM_PROGRAM_ID;
+        let account = AccountInfo::new(
+            &pda,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &current_owner,
+            false,
+            0,
+        );
+
+        crate::mock::set_mock_handler(system_program);
+        if !cfg!(feature = "panic-on-cpi-error") {
+            let unsigned = allocate_and_assign(&account, 64, &owner, &[]);
+            assert_eq!(unsigned, Err(ProgramError::MissingRequiredSignature));
+        }
+        let result = allocate_and_assign(&account, 64, &owner, &[&[b"state", &[bump]]]);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(account.data_len(), 64);
+        assert_eq!(*account.owner, owner);
+    }
+
+    #[test]
+    fn test_create_account_rent_exempt() {
+        use solana_program::{
+            program_stubs::{set_syscall_stubs, SyscallStubs},
+            sysvar::rent::Rent,
+        };
+
+        struct RentStubs;
+
+        impl SyscallStubs for RentStubs {
+            fn sol_get_rent_sysvar(&self, var_addr: *mut u8) -> u64 {
+                // SAFETY: `Rent::get` passes a pointer to a `Rent`
+                unsafe { *(var_addr as *mut Rent) = Rent::default() };
+                solana_program::entrypoint::SUCCESS
+            }
+        }
+
+        let owner = Pubkey::new_unique();
+        let payer_key = Pubkey::new_unique();
+        let new_key = Pubkey::new_unique();
+        let (mut payer_lamports, mut new_lamports) = (u64::MAX, 0);
+        let (mut payer_data, mut new_data) = ([0u8; 0], [0u8; 0]);
+        // Not `&crate::SYSTEM_PROGRAM_ID`, which `assign` can't write through
+        let (payer_owner, new_owner) = (crate::SYSTEM_PROGRAM_ID, crate::SYSTEM_PROGRAM_ID);
+        let payer = AccountInfo::new(
+            &payer_key,
+            true,
+            true,
+            &mut payer_lamports,
+            &mut payer_data,
+            &payer_owner,
+            false,
+            0,
+        );
+        let new_account = AccountInfo::new(
+            &new_key,
+            true,
+            true,
+            &mut new_lamports,
+            &mut new_data,
+            &new_owner,
+            false,
+            0,
+        );
+        let minimum = Rent::default().minimum_balance(64);
+
+        crate::mock::set_mock_handler(|instruction, account_infos, _| {
+            let lamports = u64::from_le_bytes(instruction.data[4..12].try_into().unwrap());
+            let space = u64::from_le_bytes(instruction.data[12..20].try_into().unwrap());
+            let owner = Pubkey::try_from(&instruction.data[20..]).unwrap();
+            assert_eq!(
+                *instruction,
+                solana_program::system_instruction::create_account(
+                    account_infos[0].key,
+                    account_infos[1].key,
+                    lamports,
+                    space,
+                    &owner,
+                )
+            );
+            **account_infos[0].try_borrow_mut_lamports()? -= lamports;
+            **account_infos[1].try_borrow_mut_lamports()? += lamports;
+            account_infos[1].assign(&owner);
+            Ok(())
+        });
+        let _lock = crate::STUBS_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let previous_stubs = set_syscall_stubs(Box::new(RentStubs));
+        let insufficient =
+            create_account_rent_exempt(&payer, &new_account, minimum - 1, 64, &owner, &[]);
+        let sufficient = create_account_rent_exempt(&payer, &new_account, minimum, 64, &owner, &[]);
+        set_syscall_stubs(previous_stubs);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(insufficient, Err(ProgramError::AccountNotRentExempt));
+        assert_eq!(sufficient, Ok(()));
+        assert_eq!(new_account.lamports(), minimum);
+        assert_eq!(*new_account.owner, owner);
+    }
+}
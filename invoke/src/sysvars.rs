@@ -0,0 +1,171 @@
+use solana_program::{
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    sysvar,
+};
+
+/// A sysvar account that [`invoke_with_sysvars`] includes in a CPI.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sysvar {
+    Clock,
+    EpochRewards,
+    EpochSchedule,
+    Instructions,
+    LastRestartSlot,
+    Rent,
+    SlotHashes,
+    StakeHistory,
+}
+
+impl Sysvar {
+    /// The address of the sysvar account.
+    pub const fn id(self) -> Pubkey {
+        match self {
+            Sysvar::Clock => sysvar::clock::ID,
+            Sysvar::EpochRewards => sysvar::epoch_rewards::ID,
+            Sysvar::EpochSchedule => sysvar::epoch_schedule::ID,
+            Sysvar::Instructions => sysvar::instructions::ID,
+            Sysvar::LastRestartSlot => sysvar::last_restart_slot::ID,
+            Sysvar::Rent => sysvar::rent::ID,
+            Sysvar::SlotHashes => sysvar::slot_hashes::ID,
+            Sysvar::StakeHistory => sysvar::stake_history::ID,
+        }
+    }
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), but first includes the
+/// account of each of `sysvars` in the CPI, for callees that read a sysvar
+/// from its account rather than through a syscall.
+///
+/// Each sysvar account must be in `pool`, along with the other account
+/// infos of the CPI, and be owned by the sysvar program: otherwise this
+/// returns [`ProgramError::NotEnoughAccountKeys`] or
+/// [`ProgramError::InvalidAccountOwner`] before firing. A readonly account
+/// meta is appended for each sysvar the instruction does not already list,
+/// in the order of `sysvars`.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_with_sysvars(
+    instruction: &Instruction,
+    pool: &[AccountInfo],
+    sysvars: &[Sysvar],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult {
+    let mut account_metas = Vec::with_capacity(instruction.accounts.len() + sysvars.len());
+    account_metas.extend_from_slice(&instruction.accounts);
+    for sysvar in sysvars {
+        let id = sysvar.id();
+        let account_info = pool
+            .iter()
+            .find(|account_info| *account_info.key == id)
+            .ok_or(ProgramError::NotEnoughAccountKeys)?;
+        if *account_info.owner != sysvar::ID {
+            return Err(ProgramError::InvalidAccountOwner);
+        }
+        if !account_metas
+            .iter()
+            .any(|account_meta| account_meta.pubkey == id)
+        {
+            account_metas.push(AccountMeta::new_readonly(id, false));
+        }
+    }
+
+    crate::invoke_reusing(
+        &instruction.program_id,
+        &account_metas,
+        &instruction.data,
+        pool,
+        signers_seeds,
+    )
+}
+
+#[cfg(all(test, feature = "mock"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_invoke_with_rent_sysvar() {
+        let owner = Pubkey::new_unique();
+        let key = Pubkey::new_unique();
+        let rent_id = Sysvar::Rent.id();
+        let (mut lamports, mut rent_lamports, mut fake_lamports) = (0, 1, 1);
+        let (mut data, mut rent_data, mut fake_data) = ([0u8; 0], [0u8; 17], [0u8; 17]);
+        let account = AccountInfo::new(
+            &key,
+            false,
+            true,
+            &mut lamports,
+            &mut data,
+            &owner,
+            false,
+            0,
+        );
+        let rent = AccountInfo::new(
+            &rent_id,
+            false,
+            false,
+            &mut rent_lamports,
+            &mut rent_data,
+            &sysvar::ID,
+            false,
+            0,
+        );
+        let fake_rent = AccountInfo::new(
+            &rent_id,
+            false,
+            false,
+            &mut fake_lamports,
+            &mut fake_data,
+            &owner,
+            false,
+            0,
+        );
+        let instruction = Instruction::new_with_bytes(
+            Pubkey::new_unique(),
+            &[1],
+            vec![AccountMeta::new(key, false)],
+        );
+
+        // The callee requires the rent sysvar account
+        crate::mock::set_mock_handler(|instruction, account_infos, _| {
+            let rent_id = Sysvar::Rent.id();
+            assert_eq!(
+                instruction.accounts.last(),
+                Some(&AccountMeta::new_readonly(rent_id, false))
+            );
+            let listed = instruction
+                .accounts
+                .iter()
+                .filter(|meta| meta.pubkey == rent_id);
+            assert_eq!(listed.count(), 1);
+            account_infos
+                .iter()
+                .find(|account_info| *account_info.key == rent_id)
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            Ok(())
+        });
+        let pool = [account.clone(), rent];
+        let included = invoke_with_sysvars(&instruction, &pool, &[Sysvar::Rent], &[]);
+        // Already listed, the sysvar isn't listed twice
+        let mut listed = instruction.clone();
+        listed
+            .accounts
+            .push(AccountMeta::new_readonly(rent_id, false));
+        let not_duplicated = invoke_with_sysvars(&listed, &pool, &[Sysvar::Rent], &[]);
+        crate::mock::clear_mock_handler();
+
+        assert_eq!(included, Ok(()));
+        assert_eq!(not_duplicated, Ok(()));
+        assert_eq!(
+            invoke_with_sysvars(&instruction, &pool[..1], &[Sysvar::Rent], &[]),
+            Err(ProgramError::NotEnoughAccountKeys)
+        );
+        assert_eq!(
+            invoke_with_sysvars(&instruction, &[account, fake_rent], &[Sysvar::Rent], &[]),
+            Err(ProgramError::InvalidAccountOwner)
+        );
+    }
+}
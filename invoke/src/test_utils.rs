@@ -0,0 +1,20 @@
+//! Fixtures shared by the unit tests.
+
+use solana_program::{account_info::AccountInfo, pubkey::Pubkey};
+
+/// One writable, non-signer, non-executable account info per key, owned by
+/// `owner`, over the matching lamports and data.
+pub(crate) fn writable_account_infos<'a, const N: usize>(
+    keys: &'a [Pubkey],
+    lamports: &'a mut [u64],
+    data: &'a mut [[u8; N]],
+    owner: &'a Pubkey,
+) -> Vec<AccountInfo<'a>> {
+    keys.iter()
+        .zip(lamports.iter_mut())
+        .zip(data.iter_mut())
+        .map(|((key, lamports), data)| {
+            AccountInfo::new(key, false, true, lamports, data, owner, false, 0)
+        })
+        .collect()
+}
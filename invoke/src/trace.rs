@@ -0,0 +1,194 @@
+//! Off-chain `tracing` spans around CPIs, for structured traces of the CPI
+//! tree of a program under test or simulation.
+//!
+//! Each CPI fired by the invoke functions runs in a `DEBUG` span named
+//! `invoke`, with the fields
+//!
+//! - `program_id`: the callee,
+//! - `ok`: whether the CPI succeeded,
+//! - `error`: the error, if it failed,
+//! - `compute_units`: the units it consumed, if the [`mock`](crate::mock)
+//!   feature is metering simulated compute.
+//!
+//! CPIs made by a mock handler are nested in the span of their caller.
+
+use solana_program::{entrypoint::ProgramResult, pubkey::Pubkey};
+use tracing::{field::Empty, span::EnteredSpan};
+
+/// The entered span of a CPI, closed by [`CpiSpan::record`].
+pub(crate) struct CpiSpan {
+    span: EnteredSpan,
+    before: Option<u64>,
+}
+
+/// Enters the span of a CPI to `program_id`, right before firing it.
+pub(crate) fn enter(program_id: &Pubkey) -> CpiSpan {
+    let span = tracing::debug_span!(
+        "invoke",
+        program_id = %program_id,
+        ok = Empty,
+        error = Empty,
+        compute_units = Empty,
+    );
+
+    CpiSpan {
+        span: span.entered(),
+        before: remaining_compute_units(),
+    }
+}
+
+impl CpiSpan {
+    /// Records the outcome of the CPI, and exits its span.
+    pub(crate) fn record(self, result: &ProgramResult) {
+        self.span.record("ok", result.is_ok());
+        if let Err(error) = result {
+            self.span.record("error", tracing::field::display(error));
+        }
+        if let (Some(before), Some(after)) = (self.before, remaining_compute_units()) {
+            self.span
+                .record("compute_units", before.saturating_sub(after));
+        }
+    }
+}
+
+/// The simulated compute units of the `mock` feature, the only ones
+/// available off-chain.
+fn remaining_compute_units() -> Option<u64> {
+    #[cfg(feature = "mock")]
+    return crate::mock::remaining_simulated_compute();
+
+    #[cfg(not(feature = "mock"))]
+    None
+}
+
+#[cfg(all(test, feature = "mock", not(feature = "panic-on-cpi-error")))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use solana_program::{instruction::Instruction, program_error::ProgramError};
+    use tracing::{
+        field::{Field, Visit},
+        span::{Attributes, Id, Record},
+        Event, Metadata, Subscriber,
+    };
+
+    use super::*;
+    use crate::mock;
+
+    /// The fields of each span, by span id (minus one).
+    type Spans = Arc<Mutex<Vec<Vec<(&'static str, String)>>>>;
+
+    /// A subscriber recording the fields of every span, and the span each one
+    /// is nested in.
+    #[derive(Default)]
+    struct MockSubscriber {
+        spans: Spans,
+        stack: Mutex<Vec<u64>>,
+    }
+
+    struct Fields<'a>(&'a mut Vec<(&'static str, String)>);
+
+    impl Visit for Fields<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+            self.0.push((field.name(), format!("{value:?}")));
+        }
+    }
+
+    impl Subscriber for MockSubscriber {
+        fn enabled(&self, _metadata: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, span: &Attributes<'_>) -> Id {
+            let mut fields = Vec::new();
+            if let Some(&parent) = self.stack.lock().unwrap().last() {
+                fields.push(("parent", parent.to_string()));
+            }
+            span.record(&mut Fields(&mut fields));
+            let mut spans = self.spans.lock().unwrap();
+            spans.push(fields);
+            Id::from_u64(spans.len() as u64)
+        }
+
+        fn record(&self, span: &Id, values: &Record<'_>) {
+            let mut spans = self.spans.lock().unwrap();
+            values.record(&mut Fields(&mut spans[span.into_u64() as usize - 1]));
+        }
+
+        fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+        fn event(&self, _event: &Event<'_>) {}
+
+        fn enter(&self, span: &Id) {
+            self.stack.lock().unwrap().push(span.into_u64());
+        }
+
+        fn exit(&self, _span: &Id) {
+            self.stack.lock().unwrap().pop();
+        }
+    }
+
+    #[test]
+    fn test_cpi_spans() {
+        let (caller, callee) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let instruction = Instruction::new_with_bytes(caller, &[], vec![]);
+        let failing = Instruction::new_with_bytes(callee, &[1], vec![]);
+
+        // The caller makes a nested CPI to the callee, which fails on `[1]`
+        mock::set_mock_handler(|instruction, _, _| {
+            if instruction.data == [1] {
+                return Err(ProgramError::InvalidInstructionData);
+            }
+            mock::consume_simulated_compute(100);
+            let callee = instruction.accounts.first().map(|meta| meta.pubkey);
+            match callee {
+                Some(callee) => {
+                    crate::invoke(&Instruction::new_with_bytes(callee, &[], vec![]), &[])
+                }
+                None => Ok(()),
+            }
+        });
+        let subscriber = MockSubscriber::default();
+        let spans = subscriber.spans.clone();
+        let (nested, failed) = tracing::subscriber::with_default(subscriber, || {
+            mock::set_simulated_compute(10_000);
+            let mut nested = instruction.clone();
+            nested
+                .accounts
+                .push(solana_program::instruction::AccountMeta::new_readonly(
+                    callee, false,
+                ));
+            let nested = crate::invoke(&nested, &[]);
+            mock::clear_simulated_compute();
+            (nested, crate::invoke(&failing, &[]))
+        });
+        mock::clear_mock_handler();
+
+        assert_eq!(nested, Ok(()));
+        assert_eq!(failed, Err(ProgramError::InvalidInstructionData));
+        // No instruction data, and 100 units in each handler
+        let units = mock::INVOKE_UNITS + 100;
+        let spans = spans.lock().unwrap();
+        assert_eq!(
+            *spans,
+            [
+                vec![
+                    ("program_id", caller.to_string()),
+                    ("ok", "true".to_string()),
+                    ("compute_units", (2 * units).to_string()),
+                ],
+                vec![
+                    ("parent", "1".to_string()),
+                    ("program_id", callee.to_string()),
+                    ("ok", "true".to_string()),
+                    ("compute_units", units.to_string()),
+                ],
+                vec![
+                    ("program_id", callee.to_string()),
+                    ("ok", "false".to_string()),
+                    ("error", ProgramError::InvalidInstructionData.to_string()),
+                ],
+            ]
+        );
+    }
+}
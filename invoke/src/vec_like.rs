@@ -0,0 +1,166 @@
+use solana_program::{
+    account_info::AccountInfo, entrypoint::ProgramResult, instruction::AccountMeta, pubkey::Pubkey,
+};
+
+use crate::{checks, instruction_stabilizer::InstructionStabilizer};
+
+/// A contiguous buffer that can be viewed as the `StableVec`s of a
+/// `StableInstruction` without copying, such as a `Vec` or a `SmallVec`.
+///
+/// # Safety
+///
+/// For as long as `self` is borrowed, [`as_ptr`](VecLike::as_ptr) must point
+/// to [`len`](VecLike::len) initialized elements, be non-null and aligned
+/// even when empty, and [`capacity`](VecLike::capacity) must be at least
+/// `len`.
+pub unsafe trait VecLike<T> {
+    fn as_ptr(&self) -> *const T;
+
+    fn len(&self) -> usize;
+
+    fn capacity(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+unsafe impl<T> VecLike<T> for Vec<T> {
+    #[inline(always)]
+    fn as_ptr(&self) -> *const T {
+        Vec::as_ptr(self)
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    #[inline(always)]
+    fn capacity(&self) -> usize {
+        Vec::capacity(self)
+    }
+}
+
+/// A borrowed slice, e.g. account metas viewed with
+/// [`from_packed`](crate::from_packed), whose capacity is its length.
+unsafe impl<T> VecLike<T> for &[T] {
+    #[inline(always)]
+    fn as_ptr(&self) -> *const T {
+        <[T]>::as_ptr(self)
+    }
+
+    #[inline(always)]
+    fn len(&self) -> usize {
+        <[T]>::len(self)
+    }
+
+    #[inline(always)]
+    fn capacity(&self) -> usize {
+        <[T]>::len(self)
+    }
+}
+
+/// Same as [`invoke_signed`](crate::invoke_signed), for an instruction whose
+/// account metas and data live in any [`VecLike`] buffers (e.g. a
+/// `SmallVec<[AccountMeta; 8]>`) rather than in the `Vec`s of an
+/// `Instruction`. Nothing is copied or converted to a `Vec`.
+#[cfg_attr(not(target_os = "solana"), track_caller)]
+#[must_use = "the CPI may have failed"]
+pub fn invoke_signed_vec_like<A, D>(
+    program_id: &Pubkey,
+    accounts: &A,
+    data: &D,
+    account_infos: &[AccountInfo],
+    signers_seeds: &[&[&[u8]]],
+) -> ProgramResult
+where
+    A: VecLike<AccountMeta>,
+    D: VecLike<u8>,
+{
+    let stabilizer = InstructionStabilizer::stabilize_vec_like(program_id, accounts, data);
+    let stable = stabilizer.stable_instruction_ref();
+    checks::check_accounts_len(&stable.accounts)?;
+    checks::check_borrows(&stable.accounts, account_infos)?;
+
+    crate::invoke_signed_raw(stabilizer.instruction_addr(), account_infos, signers_seeds)
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::MaybeUninit;
+
+    use super::*;
+
+    /// A `SmallVec`-like stand-in with inline storage only
+    struct InlineVec<T, const N: usize> {
+        buffer: [MaybeUninit<T>; N],
+        len: usize,
+    }
+
+    impl<T, const N: usize> InlineVec<T, N> {
+        fn new(items: impl IntoIterator<Item = T>) -> InlineVec<T, N> {
+            let mut vec = InlineVec {
+                buffer: [const { MaybeUninit::uninit() }; N],
+                len: 0,
+            };
+            for item in items {
+                vec.buffer[vec.len].write(item);
+                vec.len += 1;
+            }
+            vec
+        }
+    }
+
+    unsafe impl<T, const N: usize> VecLike<T> for InlineVec<T, N> {
+        fn as_ptr(&self) -> *const T {
+            self.buffer.as_ptr() as *const T
+        }
+
+        fn len(&self) -> usize {
+            self.len
+        }
+
+        fn capacity(&self) -> usize {
+            N
+        }
+    }
+
+    #[test]
+    fn test_stabilize_vec_like() {
+        let program_id = Pubkey::new_unique();
+        let accounts: InlineVec<AccountMeta, 8> = InlineVec::new([
+            AccountMeta::new(Pubkey::new_unique(), true),
+            AccountMeta::new_readonly(Pubkey::new_unique(), false),
+        ]);
+        let data: InlineVec<u8, 8> = InlineVec::new([1, 2, 3]);
+
+        let stabilizer = InstructionStabilizer::stabilize_vec_like(&program_id, &accounts, &data);
+        let stable = stabilizer.stable_instruction_ref();
+        assert_eq!(stable.program_id, program_id);
+        assert_eq!(stable.accounts.as_ptr(), accounts.as_ptr());
+        assert_eq!(stable.accounts.len(), 2);
+        assert_eq!(&stable.data[..], &[1, 2, 3]);
+        assert_eq!(stable.data.as_ptr(), data.as_ptr());
+
+        // Empty buffers
+        let accounts: InlineVec<AccountMeta, 0> = InlineVec::new([]);
+        let data: Vec<u8> = vec![];
+        let stabilizer = InstructionStabilizer::stabilize_vec_like(&program_id, &accounts, &data);
+        let stable = stabilizer.stable_instruction_ref();
+        assert!(stable.accounts.is_empty());
+        assert!(stable.data.is_empty());
+    }
+
+    #[test]
+    fn test_invoke_too_many_accounts() {
+        let accounts = vec![
+            AccountMeta::new_readonly(Pubkey::new_unique(), false);
+            crate::MAX_CPI_INSTRUCTION_ACCOUNTS + 1
+        ];
+        assert_eq!(
+            invoke_signed_vec_like(&Pubkey::new_unique(), &accounts, &Vec::new(), &[], &[]),
+            Err(solana_program::program_error::ProgramError::InvalidArgument)
+        );
+    }
+}
@@ -1,6 +1,6 @@
 use solana_program::{
     account_info::AccountInfo, compute_units::sol_remaining_compute_units,
-    entrypoint::ProgramResult, log::sol_log, pubkey::Pubkey,
+    entrypoint::ProgramResult, instruction::AccountMeta, log::sol_log, pubkey::Pubkey,
 };
 
 solana_program::entrypoint!(process_instruction);
@@ -8,12 +8,227 @@ solana_program::entrypoint!(process_instruction);
 const FIXED_CPI_COST: u64 = 1000;
 const REMAINING_CU_COST: u64 = 100;
 
-// A simple solana program that transfers 1 lamport twice
+/// Instruction data selecting a CPI to the memo program with empty data
+pub const EMPTY_DATA_CPI: u8 = 1;
+
+/// The SPL memo program, which `solana-program-test` loads at genesis
+pub const MEMO_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr");
+
+/// Instruction data selecting a CPI to the token program that sets return data
+pub const CAPTURE_RETURN_DATA: u8 = 2;
+
+/// The SPL token program, which `solana-program-test` loads at genesis
+pub const TOKEN_PROGRAM_ID: Pubkey =
+    solana_program::pubkey!("TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA");
+
+/// Instruction data selecting a CPI to the token program whose return data is
+/// forwarded as this program's
+pub const FORWARD_RETURN_DATA: u8 = 3;
+
+/// Length of an SPL token account, as returned by `GetAccountDataSize`
+pub const TOKEN_ACCOUNT_LEN: u64 = 165;
+
+/// Instruction data selecting the signer seeds benchmark, followed by the
+/// bumps of the `[SIGNER_SEED, &[i]]` PDAs for `i` in `0..MAX_BENCH_SIGNERS`
+pub const SIGNER_SEEDS_BENCH: u8 = 4;
+
+/// The first seed of the signer seeds benchmark's PDAs
+pub const SIGNER_SEED: &[u8] = b"signer";
+
+/// The largest number of signer groups benchmarked, the runtime's limit
+pub const MAX_BENCH_SIGNERS: usize = solana_invoke::MAX_SIGNERS;
+
+/// Instruction data selecting a memo CPI fired with `invoke_with_receipt`
+pub const INVOKE_WITH_RECEIPT: u8 = 5;
+
+/// Instruction data selecting a CPI to the program in `accounts[0]`, asking it
+/// to run `EMPTY_DATA_CPI` with the remaining accounts
+pub const NESTED_EMPTY_DATA_CPI: u8 = 6;
+
+/// Instruction data selecting the same memo CPI fired through a `Vec`-based
+/// instruction and through a `CompactInstruction`
+pub const COMPACT_BENCH: u8 = 7;
+
 fn process_instruction(
-    _program_id: &Pubkey,
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    match data.first() {
+        Some(&EMPTY_DATA_CPI) => empty_data_cpi(accounts),
+        Some(&CAPTURE_RETURN_DATA) => capture_return_data(accounts),
+        Some(&FORWARD_RETURN_DATA) => forward_return_data(accounts),
+        Some(&SIGNER_SEEDS_BENCH) => signer_seeds_bench(program_id, accounts, &data[1..]),
+        Some(&INVOKE_WITH_RECEIPT) => invoke_with_receipt(accounts),
+        Some(&NESTED_EMPTY_DATA_CPI) => nested_empty_data_cpi(accounts),
+        Some(&COMPACT_BENCH) => compact_bench(accounts),
+        _ => triple_transfer(accounts),
+    }
+}
+
+// Invokes the memo program with no accounts and empty data, which the memo
+// program accepts. This exercises stabilizing empty `Vec`s.
+fn empty_data_cpi(accounts: &[AccountInfo]) -> ProgramResult {
+    let memo = solana_program::instruction::Instruction {
+        program_id: MEMO_PROGRAM_ID,
+        accounts: vec![],
+        data: vec![],
+    };
+    solana_invoke::invoke(&memo, accounts)?;
+    solana_invoke::invoke_unchecked(&memo, accounts)
+}
+
+// Asks the token program for the size of a token account of the mint in
+// `accounts[0]`, which it reports through return data. The same buffer is
+// reused across both CPIs.
+fn capture_return_data(accounts: &[AccountInfo]) -> ProgramResult {
+    let get_account_data_size = solana_program::instruction::Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![AccountMeta::new_readonly(*accounts[0].key, false)],
+        // `TokenInstruction::GetAccountDataSize`
+        data: vec![21],
+    };
+    let mut out = Vec::new();
+    for _ in 0..2 {
+        let program_id =
+            solana_invoke::invoke_capturing(&get_account_data_size, accounts, &mut out)?;
+        assert_eq!(program_id, TOKEN_PROGRAM_ID);
+        assert_eq!(out, TOKEN_ACCOUNT_LEN.to_le_bytes());
+    }
+    Ok(())
+}
+
+// Same CPI as `capture_return_data`, exposing the token program's return
+// data to this program's caller.
+fn forward_return_data(accounts: &[AccountInfo]) -> ProgramResult {
+    let get_account_data_size = solana_program::instruction::Instruction {
+        program_id: TOKEN_PROGRAM_ID,
+        accounts: vec![AccountMeta::new_readonly(*accounts[0].key, false)],
+        // `TokenInstruction::GetAccountDataSize`
+        data: vec![21],
+    };
+    solana_invoke::invoke_and_forward_return_data(&get_account_data_size, accounts, &[])
+}
+
+// Invokes the memo program signed by 1, 4 and 16 PDAs, with the signer seeds
+// scaffolding built on the stack and then on the heap. Each measurement
+// includes building the seeds, so the difference is the cost of the heap.
+fn signer_seeds_bench(
+    program_id: &Pubkey,
     accounts: &[AccountInfo],
-    _data: &[u8],
+    bumps: &[u8],
 ) -> ProgramResult {
+    const INDICES: [u8; MAX_BENCH_SIGNERS] = {
+        let mut indices = [0; MAX_BENCH_SIGNERS];
+        let mut i = 0;
+        while i < MAX_BENCH_SIGNERS {
+            indices[i] = i as u8;
+            i += 1;
+        }
+        indices
+    };
+    let memo = solana_program::instruction::Instruction {
+        program_id: MEMO_PROGRAM_ID,
+        accounts: vec![],
+        data: vec![],
+    };
+    let seeds = |i: usize| [SIGNER_SEED, &INDICES[i..i + 1], &bumps[i..i + 1]];
+    debug_assert!(Pubkey::create_program_address(&seeds(0), program_id).is_ok());
+
+    for num_signers in [1, 4, MAX_BENCH_SIGNERS] {
+        let first = sol_remaining_compute_units();
+        let stack_seeds: [[&[u8]; 3]; MAX_BENCH_SIGNERS] = core::array::from_fn(seeds);
+        let stack_groups: [&[&[u8]]; MAX_BENCH_SIGNERS] =
+            core::array::from_fn(|i| &stack_seeds[i][..]);
+        solana_invoke::invoke_signed(&memo, accounts, &stack_groups[..num_signers])?;
+        let second = sol_remaining_compute_units();
+        sol_log(&format!(
+            "invoked memo program with {num_signers} signers (stack seeds) successfully: {} cus",
+            first - second - FIXED_CPI_COST - REMAINING_CU_COST,
+        ));
+
+        let first = sol_remaining_compute_units();
+        let heap_seeds: Vec<Vec<&[u8]>> = (0..num_signers).map(|i| seeds(i).to_vec()).collect();
+        let heap_groups: Vec<&[&[u8]]> = heap_seeds.iter().map(Vec::as_slice).collect();
+        solana_invoke::invoke_signed(&memo, accounts, &heap_groups)?;
+        let second = sol_remaining_compute_units();
+        sol_log(&format!(
+            "invoked memo program with {num_signers} signers (heap seeds) successfully: {} cus",
+            first - second - FIXED_CPI_COST - REMAINING_CU_COST,
+        ));
+    }
+
+    Ok(())
+}
+
+// Invokes the memo program with a memo and checks the receipt, logging the
+// compute units it reports.
+fn invoke_with_receipt(accounts: &[AccountInfo]) -> ProgramResult {
+    let memo = solana_program::instruction::Instruction {
+        program_id: MEMO_PROGRAM_ID,
+        accounts: vec![],
+        data: b"receipt".to_vec(),
+    };
+    let receipt = solana_invoke::invoke_with_receipt(&memo, accounts, &[])?;
+    assert_eq!(receipt.program_id, MEMO_PROGRAM_ID);
+    assert_eq!(receipt.accounts_len, 0);
+    assert_eq!(receipt.data_len, 7);
+    assert!(receipt.compute_units > FIXED_CPI_COST);
+    sol_log(&format!(
+        "invoked memo program with a receipt: {} cus",
+        receipt.compute_units
+    ));
+    Ok(())
+}
+
+// Invokes another deployment of this program (e.g. one owned by the
+// upgradeable loader) with `EMPTY_DATA_CPI`. Only the program account is
+// passed, never a program data account.
+fn nested_empty_data_cpi(accounts: &[AccountInfo]) -> ProgramResult {
+    let (program, accounts) = accounts.split_first().unwrap();
+    let nested = solana_program::instruction::Instruction {
+        program_id: *program.key,
+        accounts: accounts
+            .iter()
+            .map(|account| AccountMeta::new_readonly(*account.key, false))
+            .collect(),
+        data: vec![EMPTY_DATA_CPI],
+    };
+    solana_invoke::invoke(&nested, accounts)
+}
+
+// Invokes the memo program with a 2-byte memo, through a `Vec`-based
+// instruction and then a `CompactInstruction`. Each measurement includes
+// building the instruction, so the difference is the cost of the `Vec`s.
+fn compact_bench(accounts: &[AccountInfo]) -> ProgramResult {
+    let first = sol_remaining_compute_units();
+    let memo = solana_program::instruction::Instruction {
+        program_id: MEMO_PROGRAM_ID,
+        accounts: vec![],
+        data: b"gm".to_vec(),
+    };
+    solana_invoke::invoke(&memo, accounts)?;
+    let second = sol_remaining_compute_units();
+    sol_log(&format!(
+        "invoked memo program via the vec instruction successfully: {} cus",
+        first - second - FIXED_CPI_COST - REMAINING_CU_COST,
+    ));
+
+    let first = sol_remaining_compute_units();
+    let memo = solana_invoke::CompactInstruction::new(MEMO_PROGRAM_ID, [], *b"gm");
+    solana_invoke::invoke_compact(&memo, accounts, &[])?;
+    let second = sol_remaining_compute_units();
+    sol_log(&format!(
+        "invoked memo program via the compact instruction successfully: {} cus",
+        first - second - FIXED_CPI_COST - REMAINING_CU_COST,
+    ));
+
+    Ok(())
+}
+
+// A simple solana program that transfers 1 lamport twice
+fn triple_transfer(accounts: &[AccountInfo]) -> ProgramResult {
     let original_balance = accounts[0].lamports();
     // Send from account zero to account one, thrice.
     // 1) First with standard invoke.
@@ -55,14 +270,68 @@ fn process_instruction(
         first - second - FIXED_CPI_COST - REMAINING_CU_COST,
     ));
 
+    // 4) Then with our invoke_signed_c, padding the account infos with
+    // duplicates of the system program to compare stack marshaling (up to
+    // C_ABI_STACK_ACCOUNTS infos) against heap marshaling (above it)
+    let mut expected_balance = original_balance - 3;
+    for num_infos in [2, solana_invoke::C_ABI_STACK_ACCOUNTS, 16, 32] {
+        let infos: Vec<AccountInfo> = accounts[..2]
+            .iter()
+            .chain(core::iter::repeat(&accounts[2]))
+            .take(num_infos)
+            .cloned()
+            .collect();
+        sol_log("invoking system program via our invoke_signed_c");
+        let first = sol_remaining_compute_units();
+        solana_invoke::invoke_signed_c(&transfer, &infos, &[])?;
+        let second = sol_remaining_compute_units();
+        expected_balance -= 1;
+        assert_eq!(accounts[0].lamports(), expected_balance);
+        sol_log(&format!(
+            "invoked system program via our invoke_signed_c ({} infos, {}) successfully: {} cus",
+            num_infos,
+            if num_infos <= solana_invoke::C_ABI_STACK_ACCOUNTS {
+                "stack"
+            } else {
+                "heap"
+            },
+            first - second - FIXED_CPI_COST - REMAINING_CU_COST,
+        ));
+    }
+
+    // 5) Then compare our invoke against invoke_fast on a transfer that also
+    // passes the (readonly, executable) system program account
+    let mut transfer_with_program = transfer.clone();
+    transfer_with_program
+        .accounts
+        .push(AccountMeta::new_readonly(*accounts[2].key, false));
+    for (name, invoke) in [
+        ("invoke", solana_invoke::invoke as fn(_, _) -> _),
+        ("invoke_fast", solana_invoke::invoke_fast),
+    ] {
+        sol_log(&format!("invoking system program via our {name}"));
+        let first = sol_remaining_compute_units();
+        invoke(&transfer_with_program, &accounts[..3])?;
+        let second = sol_remaining_compute_units();
+        expected_balance -= 1;
+        assert_eq!(accounts[0].lamports(), expected_balance);
+        sol_log(&format!(
+            "invoked system program via our {name} with a readonly program account successfully: {} cus",
+            first - second - FIXED_CPI_COST - REMAINING_CU_COST,
+        ));
+    }
+
     Ok(())
 }
 
 #[cfg(test)]
+#[allow(deprecated)]
 mod tests {
-    use solana_program_test::ProgramTest;
+    use solana_program_test::{ProgramTest, ProgramTestContext};
     use solana_sdk::{
         account::AccountSharedData,
+        bpf_loader_upgradeable,
+        compute_budget::ComputeBudgetInstruction,
         instruction::{AccountMeta, Instruction},
         pubkey::Pubkey,
         signature::Keypair,
@@ -114,4 +383,287 @@ mod tests {
             .await
             .unwrap();
     }
+
+    #[tokio::test]
+    async fn test_empty_data_cpi() {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_program("triple_transfer", program_id, None);
+        let mut ctx = program_test.start_with_context().await;
+
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new_readonly(super::MEMO_PROGRAM_ID, false)],
+            data: vec![super::EMPTY_DATA_CPI],
+        };
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+
+        ctx.banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_capture_return_data() {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_program("triple_transfer", program_id, None);
+        let mut ctx = program_test.start_with_context().await;
+        let mint = set_initialized_mint(&mut ctx);
+
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(super::TOKEN_PROGRAM_ID, false),
+            ],
+            data: vec![super::CAPTURE_RETURN_DATA],
+        };
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+
+        ctx.banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_forward_return_data() {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_program("triple_transfer", program_id, None);
+        let mut ctx = program_test.start_with_context().await;
+        let mint = set_initialized_mint(&mut ctx);
+
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new_readonly(mint, false),
+                AccountMeta::new_readonly(super::TOKEN_PROGRAM_ID, false),
+            ],
+            data: vec![super::FORWARD_RETURN_DATA],
+        };
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+
+        let result = ctx
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await
+            .unwrap();
+        assert_eq!(result.result, Ok(()));
+
+        // Set by this program, not the token program
+        let return_data = result.metadata.unwrap().return_data.unwrap();
+        assert_eq!(return_data.program_id, program_id);
+        assert_eq!(return_data.data, super::TOKEN_ACCOUNT_LEN.to_le_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_signer_seeds_bench() {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_program("triple_transfer", program_id, None);
+        let mut ctx = program_test.start_with_context().await;
+
+        let mut data = vec![super::SIGNER_SEEDS_BENCH];
+        data.extend(
+            (0..super::MAX_BENCH_SIGNERS as u8)
+                .map(|i| Pubkey::find_program_address(&[super::SIGNER_SEED, &[i]], &program_id).1),
+        );
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new_readonly(super::MEMO_PROGRAM_ID, false)],
+            data,
+        };
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[
+                ComputeBudgetInstruction::set_compute_unit_limit(1_400_000),
+                instruction,
+            ],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+
+        let result = ctx
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await
+            .unwrap();
+        assert_eq!(result.result, Ok(()));
+
+        let logs = result.metadata.unwrap().log_messages;
+        let cus = |num_signers: usize, approach: &str| -> u64 {
+            let prefix = format!(
+                "Program log: invoked memo program with {num_signers} signers ({approach} seeds) successfully: "
+            );
+            let line = logs.iter().find_map(|log| log.strip_prefix(&prefix));
+            line.and_then(|cus| cus.strip_suffix(" cus"))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        for num_signers in [1, 4, super::MAX_BENCH_SIGNERS] {
+            let (stack, heap) = (cus(num_signers, "stack"), cus(num_signers, "heap"));
+            println!("{num_signers} signers: stack {stack} cus, heap {heap} cus");
+            assert!(
+                stack < heap,
+                "{num_signers} signers: stack {stack} cus, heap {heap} cus"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compact_bench() {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_program("triple_transfer", program_id, None);
+        let mut ctx = program_test.start_with_context().await;
+
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new_readonly(super::MEMO_PROGRAM_ID, false)],
+            data: vec![super::COMPACT_BENCH],
+        };
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+
+        let result = ctx
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await
+            .unwrap();
+        assert_eq!(result.result, Ok(()));
+
+        let logs = result.metadata.unwrap().log_messages;
+        let cus = |approach: &str| -> u64 {
+            let prefix = format!(
+                "Program log: invoked memo program via the {approach} instruction successfully: "
+            );
+            let line = logs.iter().find_map(|log| log.strip_prefix(&prefix));
+            line.and_then(|cus| cus.strip_suffix(" cus"))
+                .unwrap()
+                .parse()
+                .unwrap()
+        };
+        let (vec, compact) = (cus("vec"), cus("compact"));
+        println!("vec {vec} cus, compact {compact} cus");
+        assert!(compact < vec, "vec {vec} cus, compact {compact} cus");
+    }
+
+    #[tokio::test]
+    async fn test_invoke_with_receipt() {
+        let program_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_program("triple_transfer", program_id, None);
+        let mut ctx = program_test.start_with_context().await;
+
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![AccountMeta::new_readonly(super::MEMO_PROGRAM_ID, false)],
+            data: vec![super::INVOKE_WITH_RECEIPT],
+        };
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+
+        let result = ctx
+            .banks_client
+            .process_transaction_with_metadata(transaction)
+            .await
+            .unwrap();
+        assert_eq!(result.result, Ok(()));
+        let logs = result.metadata.unwrap().log_messages;
+        assert!(logs
+            .iter()
+            .any(|log| log.starts_with("Program log: invoked memo program with a receipt: ")));
+    }
+
+    #[tokio::test]
+    async fn test_cpi_to_upgradeable_program() {
+        // The callee is deployed with the upgradeable loader, with a separate
+        // program data account
+        let program_id = Pubkey::new_unique();
+        let upgradeable_id = Pubkey::new_unique();
+        let mut program_test = ProgramTest::default();
+        program_test.prefer_bpf(true);
+        program_test.add_program("triple_transfer", program_id, None);
+        program_test.add_upgradeable_program_to_genesis("triple_transfer", &upgradeable_id);
+        let mut ctx = program_test.start_with_context().await;
+        let upgradeable = ctx
+            .banks_client
+            .get_account(upgradeable_id)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(upgradeable.owner, bpf_loader_upgradeable::ID);
+
+        let instruction = Instruction {
+            program_id,
+            accounts: vec![
+                AccountMeta::new_readonly(upgradeable_id, false),
+                AccountMeta::new_readonly(super::MEMO_PROGRAM_ID, false),
+            ],
+            data: vec![super::NESTED_EMPTY_DATA_CPI],
+        };
+        let blockhash = ctx.get_new_latest_blockhash().await.unwrap();
+        let transaction = Transaction::new_signed_with_payer(
+            &[instruction],
+            Some(&ctx.payer.pubkey()),
+            &[&ctx.payer],
+            blockhash,
+        );
+
+        ctx.banks_client
+            .process_transaction(transaction)
+            .await
+            .unwrap();
+    }
+
+    /// Sets an initialized `spl_token::state::Mint` without authorities: only
+    /// `is_initialized` (after the mint authority, supply and decimals) is set
+    fn set_initialized_mint(ctx: &mut ProgramTestContext) -> Pubkey {
+        let mint = Pubkey::new_unique();
+        let mut mint_data = vec![0u8; 82];
+        mint_data[4 + 32 + 8 + 1] = 1;
+        let mut mint_account = AccountSharedData::new(1_000_000_000, 82, &super::TOKEN_PROGRAM_ID);
+        mint_account.set_data_from_slice(&mint_data);
+        ctx.set_account(&mint, &mint_account);
+        mint
+    }
 }